@@ -0,0 +1,113 @@
+//! Times each phase of a step while generating 64x64 maps, and compares generating them with
+//! [AdjacencyRules] and their [CompiledRules], run with `cargo bench --features bench`
+//!
+//! The phases are called the same way [Wfc::step_diff_with] calls them, so their times add up
+//! to the time taken by stepping
+
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use wfc::wfc::bench::{break_tie, collapse, lowest_entropy_tiles};
+use wfc::wfc::{AdjacencyRules, CompiledRules, Direction, State, StateIndex, Tile, Wfc, WfcRules};
+
+const SIZE: usize = 64;
+const RUNS: u64 = 8;
+
+/// The height of the terrain at a tile
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+struct Level(u8);
+
+impl State for Level {}
+
+impl StateIndex for Level {
+    const COUNT: usize = 4;
+
+    fn index(&self) -> usize {
+        self.0 as usize
+    }
+
+    fn from_index(i: usize) -> Self {
+        assert!(i < Self::COUNT, "there are only four levels");
+        Level(i as u8)
+    }
+}
+
+/// Rules where neighbors differ by at most one level, which leaves plenty of choice at every
+/// step
+fn rules() -> AdjacencyRules<Level> {
+    let mut rules = AdjacencyRules::new();
+    for a in 0..4u8 {
+        for b in a.saturating_sub(1)..(a + 2).min(4) {
+            rules.allow(Level(a), Direction::East, Level(b));
+            rules.allow(Level(a), Direction::South, Level(b));
+        }
+    }
+    rules
+}
+
+fn map<R: WfcRules<Level>>(rules: R) -> Wfc<Level, R> {
+    let states = (0..4).map(Level).collect();
+    let tiles = vec![Tile::Indefinite(states); SIZE * SIZE];
+    Wfc::new(SIZE, SIZE, tiles, rules)
+}
+
+/// Returns the average time taken to step through a map with [rules] over [RUNS] seeds
+fn generate<R: WfcRules<Level> + Clone>(rules: &R) -> Duration {
+    let mut total = Duration::ZERO;
+    for seed in 0..RUNS {
+        let mut wfc = map(rules.clone());
+        let mut rng = StdRng::seed_from_u64(seed);
+        let start = Instant::now();
+        while let Ok(true) = wfc.try_step_with(&mut rng) {}
+        total += start.elapsed();
+    }
+    total / RUNS as u32
+}
+
+fn main() {
+    let mut phases = [Duration::ZERO; 4];
+    let mut steps = 0;
+    for seed in 0..RUNS {
+        let mut wfc = map(rules());
+        let mut rng = StdRng::seed_from_u64(seed);
+        loop {
+            let start = Instant::now();
+            let tied = lowest_entropy_tiles(&wfc, &mut rng).unwrap();
+            phases[0] += start.elapsed();
+
+            let start = Instant::now();
+            let selected = break_tie(&wfc, &tied, &mut rng);
+            phases[1] += start.elapsed();
+            let selected = match selected {
+                Some(selected) => selected,
+                None => break,
+            };
+
+            let start = Instant::now();
+            let collapsed = collapse(&mut wfc, selected, &mut rng);
+            phases[2] += start.elapsed();
+            if collapsed.is_err() {
+                break; // A contradiction, which stepping reports as an error
+            }
+
+            let start = Instant::now();
+            let propagated = wfc.propagate_at(selected);
+            phases[3] += start.elapsed();
+            if propagated.is_err() {
+                break;
+            }
+            steps += 1;
+        }
+    }
+
+    let rules = rules();
+    let compiled: CompiledRules<Level> = rules.compile();
+
+    println!("{} runs of a {}x{} map, {} steps", RUNS, SIZE, SIZE, steps);
+    for (name, time) in ["entropy", "selection", "collapse", "propagation"].into_iter().zip(phases) {
+        println!("{:>12}: {:?}", name, time / RUNS as u32);
+    }
+    println!("{:>12}: {:?}", "whole step", generate(&rules));
+    println!("{:>12}: {:?}", "compiled", generate(&compiled));
+}
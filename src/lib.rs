@@ -0,0 +1,7 @@
+//! Wave function collapse, with a sudoku solver built on it in the binary
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod wfc;
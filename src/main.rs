@@ -1,10 +1,91 @@
-use std::collections::{BTreeSet, HashSet};
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
 
-use crate::wfc::{State, Tile, WfcRules, WfcView};
+use ::wfc::wfc;
 
-mod wfc;
+use crate::wfc::prelude::*;
+use crate::wfc::{FullStates, StateIndex};
 
-pub struct SudokuRules;
+/// The rules of sudoku, where each row, column and [block_w]x[block_h] block contains every
+/// digit exactly once
+#[derive(Debug, Clone)]
+pub struct SudokuRules {
+    block_w: usize,
+    block_h: usize,
+}
+
+impl SudokuRules {
+    /// Creates the rules for a sudoku made of [block_w]x[block_h] blocks
+    ///
+    /// # Panics
+    /// * If [block_w] or [block_h] is zero
+    /// * If a block would need more than nine digits
+    pub fn new(block_w: usize, block_h: usize) -> Self {
+        assert!(block_w > 0 && block_h > 0, "blocks cannot be empty");
+        assert!(block_w * block_h <= 9, "blocks cannot hold more than nine digits");
+        Self { block_w, block_h }
+    }
+
+    /// Returns the digits used by these rules, one for each cell in a block
+    pub fn states(&self) -> BTreeSet<SudokuNum> {
+        SudokuNum::all()
+            .into_iter()
+            .take(self.block_w * self.block_h)
+            .collect()
+    }
+
+    /// Creates a sudoku from [tiles], returning [WfcError::InvalidDimensions] if the map cannot
+    /// be divided evenly into blocks
+    pub fn wfc(self, width: usize, height: usize, tiles: Vec<Tile<SudokuNum>>) -> Result<Wfc<SudokuNum, Self>, WfcError> {
+        if !width.is_multiple_of(self.block_w) || !height.is_multiple_of(self.block_h) {
+            return Err(WfcError::InvalidDimensions);
+        }
+        Wfc::try_new(width, height, tiles, self)
+    }
+}
+
+impl SudokuRules {
+    /// Returns each digit ruled out of the tile at [map.pos()] along with the row, column or
+    /// block that ruled it out, to find why a puzzle has no solution
+    ///
+    /// A digit appears once for every one of the tile's units that already holds it, ordered
+    /// by unit and then by digit. The tile's own digit counts too if it is definite
+    pub fn explain(&self, map: View<'_>) -> Vec<(Constraint, SudokuNum)> {
+        let (x, y) = *map.pos();
+        let units = [
+            (Constraint::Row(y), map.row()),
+            (Constraint::Column(x), map.col()),
+            (
+                Constraint::Block(x / self.block_w, y / self.block_h),
+                map.section_at(self.block_w, self.block_h, x, y),
+            ),
+        ];
+
+        let mut eliminated = Vec::new();
+        for (constraint, span) in units {
+            let digits = span.definite_iter().cloned().collect::<BTreeSet<_>>();
+            eliminated.extend(digits.into_iter().map(|digit| (constraint, digit)));
+        }
+        eliminated
+    }
+}
+
+/// One of the units of a sudoku that must hold every digit exactly once
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Constraint {
+    /// The row at the y
+    Row(usize),
+    /// The column at the x
+    Column(usize),
+    /// The block at the x & y counted in blocks, so (1, 0) is the second block of the top row
+    Block(usize, usize),
+}
+
+impl Default for SudokuRules {
+    fn default() -> Self {
+        Self::new(3, 3)
+    }
+}
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub enum SudokuNum {
@@ -20,7 +101,14 @@ pub enum SudokuNum {
 }
 
 impl SudokuNum {
+    /// Returns every digit, the same as [FullStates::all]
     pub fn full_set() -> BTreeSet<Self> {
+        Self::all()
+    }
+}
+
+impl FullStates for SudokuNum {
+    fn all() -> BTreeSet<Self> {
         BTreeSet::from([
             Self::One,
             Self::Two,
@@ -35,6 +123,65 @@ impl SudokuNum {
     }
 }
 
+impl From<SudokuNum> for u8 {
+    fn from(num: SudokuNum) -> Self {
+        match num {
+            SudokuNum::One => 1,
+            SudokuNum::Two => 2,
+            SudokuNum::Three => 3,
+            SudokuNum::Four => 4,
+            SudokuNum::Five => 5,
+            SudokuNum::Six => 6,
+            SudokuNum::Seven => 7,
+            SudokuNum::Eight => 8,
+            SudokuNum::Nine => 9,
+        }
+    }
+}
+
+/// The error returned when converting a number outside of 1..=9 into a [SudokuNum]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InvalidDigit(pub u8);
+
+impl TryFrom<u8> for SudokuNum {
+    type Error = InvalidDigit;
+
+    fn try_from(digit: u8) -> Result<Self, Self::Error> {
+        Ok(match digit {
+            1 => Self::One,
+            2 => Self::Two,
+            3 => Self::Three,
+            4 => Self::Four,
+            5 => Self::Five,
+            6 => Self::Six,
+            7 => Self::Seven,
+            8 => Self::Eight,
+            9 => Self::Nine,
+            _ => return Err(InvalidDigit(digit)),
+        })
+    }
+}
+
+impl StateIndex for SudokuNum {
+    const COUNT: usize = 9;
+
+    fn index(&self) -> usize {
+        u8::from(self.clone()) as usize - 1
+    }
+
+    fn from_index(i: usize) -> Self {
+        u8::try_from(i + 1).ok()
+            .and_then(|digit| Self::try_from(digit).ok())
+            .expect("sudoku numbers only have indices 0..9")
+    }
+}
+
+impl Display for SudokuNum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", u8::from(self.clone()))
+    }
+}
+
 type View<'a> = WfcView<'a, SudokuNum, SudokuRules>;
 
 // impl SudokuRules {
@@ -57,32 +204,388 @@ type View<'a> = WfcView<'a, SudokuNum, SudokuRules>;
 impl State for SudokuNum {}
 
 impl WfcRules<SudokuNum> for SudokuRules {
-    fn get_states(&self, map: View<'_>) -> BTreeSet<SudokuNum> {
-        fn states<'a, I: Iterator<Item=&'a Tile<SudokuNum>>>(i: I) -> BTreeSet<&'a SudokuNum> {
-            i.filter_map(|tile| match tile {
-                Tile::Definite(s) => Some(s),
-                Tile::Indefinite(_) => None
-            }).collect()
-        }
+    type Cache = ();
 
+    fn get_states(&self, map: View<'_>) -> BTreeSet<SudokuNum> {
         let row = map.row();
         let col = map.col();
         let (x, y) = map.pos();
-        let block = map.section_at(3, 3, *x, *y);
-
-        let row_states = states(row.row_iter());
-        let col_states = states(col.row_iter());
-        let block_states = states(block.row_iter());
-        let mut possible = SudokuNum::full_set();
-        for states in [row_states, col_states, block_states] {
-            for state in states {
+        let block = map.section_at(self.block_w, self.block_h, *x, *y);
+
+        let mut possible = self.states();
+        for span in [row, col, block] {
+            for state in span.definite_iter() {
                 possible.remove(state);
             }
         }
         possible
     }
+
+    /// Tiles with fewer candidates are collapsed first, so guesses are less likely to be wrong
+    fn entropy(&self, tile: &Tile<SudokuNum>) -> f64 {
+        match tile {
+            Tile::Definite(_) => 0.0,
+            Tile::Indefinite(states) => states.len() as f64,
+        }
+    }
+
+    /// Only the tiles sharing a row, column or block can be affected
+    fn dependents(&self, map: View<'_>) -> Vec<usize> {
+        let (x, y) = *map.pos();
+        let (bx, by) = (x / self.block_w * self.block_w, y / self.block_h * self.block_h);
+        let mut dependents = (0..map.width()).map(|x| map.index_of(x, y))
+            .chain((0..map.height()).map(|y| map.index_of(x, y)))
+            .chain((by..by + self.block_h).flat_map(|y| (bx..bx + self.block_w).map(move |x| (x, y)))
+                .filter(|(x, y)| *x < map.width() && *y < map.height())
+                .map(|(x, y)| map.index_of(x, y)))
+            .filter(|idx| *idx != map.self_index())
+            .collect::<Vec<_>>();
+        dependents.sort();
+        dependents.dedup();
+        dependents
+    }
 }
 
 fn main() {
     println!("Hello, world!");
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::{Constraint, InvalidDigit, SudokuNum, SudokuRules};
+    use crate::wfc::{FullStates, SolveStats, StateIndex, Tile, Wfc, WfcError, WfcRules};
+
+    #[test]
+    fn u8_round_trip() {
+        for (digit, num) in (1..=9).zip(SudokuNum::full_set()) {
+            assert_eq!(SudokuNum::try_from(digit), Ok(num.clone()));
+            assert_eq!(u8::from(num.clone()), digit);
+            assert_eq!(num.to_string(), digit.to_string());
+        }
+    }
+
+    #[test]
+    fn index_round_trip() {
+        for (i, num) in SudokuNum::full_set().into_iter().enumerate() {
+            assert_eq!(num.index(), i);
+            assert_eq!(SudokuNum::from_index(i), num);
+        }
+        assert_eq!(SudokuNum::full_set().len(), SudokuNum::COUNT);
+    }
+
+    #[test]
+    fn explain() {
+        use SudokuNum::*;
+
+        let mut tiles = vec![Tile::Indefinite(SudokuNum::full_set()); 81];
+        let givens = [(1, One), (2, Two), (3, Three), (4, Four), (27, Five), (36, Six), (10, Seven), (11, Eight), (20, Nine)];
+        for (idx, num) in givens {
+            tiles[idx] = Tile::Definite(num);
+        }
+        let wfc = Wfc::new(9, 9, tiles, SudokuRules::default());
+        let rules = wfc.view(0).rules();
+        assert_eq!(rules.get_states(wfc.view(0)), BTreeSet::new());
+
+        let explanation = rules.explain(wfc.view(0));
+        assert_eq!(explanation, [
+            (Constraint::Row(0), One),
+            (Constraint::Row(0), Two),
+            (Constraint::Row(0), Three),
+            (Constraint::Row(0), Four),
+            (Constraint::Column(0), Five),
+            (Constraint::Column(0), Six),
+            (Constraint::Block(0, 0), One),
+            (Constraint::Block(0, 0), Two),
+            (Constraint::Block(0, 0), Seven),
+            (Constraint::Block(0, 0), Eight),
+            (Constraint::Block(0, 0), Nine),
+        ]);
+
+        // Every missing digit is explained by some unit
+        let explained = explanation.into_iter().map(|(_, num)| num).collect::<BTreeSet<_>>();
+        assert_eq!(explained, SudokuNum::full_set());
+    }
+
+    #[test]
+    fn new_full() {
+        let wfc = Wfc::new_full(9, 9, SudokuRules::default());
+        assert_eq!(SudokuNum::all(), SudokuNum::full_set());
+        assert!(wfc.tiles().iter().all(|tile| tile == &Tile::Indefinite(SudokuNum::full_set())));
+        assert_eq!(wfc.tiles().len(), 81);
+    }
+
+    #[test]
+    fn map_to_u8() {
+        let mut tiles = vec![Tile::Indefinite(SudokuNum::full_set()); 81];
+        tiles[0] = Tile::Definite(SudokuNum::Seven);
+        tiles[1] = Tile::Indefinite(BTreeSet::from([SudokuNum::One, SudokuNum::Nine]));
+        let wfc = Wfc::new(9, 9, tiles, SudokuRules::default());
+
+        let digits = wfc.map(|num| u8::from(num.clone()));
+        assert_eq!(digits.len(), 81);
+        assert_eq!(digits[0], Tile::Definite(7));
+        assert_eq!(digits[1], Tile::Indefinite(BTreeSet::from([1, 9])));
+        assert_eq!(digits[2], Tile::Indefinite((1..=9).collect()));
+        assert!(digits.iter().zip(wfc.tiles()).all(|(digit, num)| digit.is_definite() == num.is_definite()));
+    }
+
+    #[test]
+    fn frozen_givens() {
+        let givens = [(0, SudokuNum::Five), (40, SudokuNum::Three)];
+        let mut wfc = Wfc::with_givens(9, 9, SudokuNum::full_set(), SudokuRules::default(), &givens).unwrap();
+        for (idx, _) in &givens {
+            wfc.freeze(*idx).unwrap();
+        }
+        assert_eq!(wfc.freeze(1), Err(WfcError::NotCollapsed(1)));
+        wfc.collapse_at(80, SudokuNum::One).unwrap();
+
+        wfc.reset_uniform(SudokuNum::full_set());
+        assert_eq!(wfc[0], Tile::Definite(SudokuNum::Five));
+        assert_eq!(wfc[40], Tile::Definite(SudokuNum::Three));
+        assert_eq!(wfc[80], Tile::Indefinite(SudokuNum::full_set()));
+        assert_eq!(wfc.indefinite_count(), 79);
+        assert!(wfc.is_frozen(0) && !wfc.is_frozen(80));
+
+        assert_eq!(wfc.collapse_at(0, SudokuNum::One), Err(WfcError::Frozen(0)));
+        assert_eq!(wfc.ban(40, &SudokuNum::Three), Err(WfcError::Frozen(40)));
+    }
+
+    #[test]
+    fn u8_out_of_range() {
+        assert_eq!(SudokuNum::try_from(0), Err(InvalidDigit(0)));
+        assert_eq!(SudokuNum::try_from(10), Err(InvalidDigit(10)));
+    }
+
+    #[test]
+    fn step_at_propagates_to_block() {
+        let tiles = vec![Tile::Indefinite(SudokuNum::full_set()); 81];
+        let mut wfc = Wfc::new(9, 9, tiles, SudokuRules::default());
+        let center = wfc.xy_pair(4, 4);
+        assert_eq!(wfc.step_at(center), Ok(()));
+
+        let state = wfc.view(center).get_at(4, 4).as_definite().clone();
+        for y in 3..6 {
+            for x in 3..6 {
+                let idx = wfc.xy_pair(x, y);
+                if idx != center {
+                    assert!(!wfc.view(idx).get_at(x, y).as_indefinite().contains(&state));
+                }
+            }
+        }
+        assert_eq!(wfc.view(0).get_at(0, 0).as_indefinite().len(), 9);
+    }
+
+    #[test]
+    fn collect_map() {
+        let givens = [(9, SudokuNum::Four), (10, SudokuNum::Two), (12, SudokuNum::Eight)];
+        let wfc = Wfc::with_givens(9, 9, SudokuNum::full_set(), SudokuRules::default(), &givens).unwrap();
+        let row = wfc.row(1);
+
+        let values: Vec<Option<SudokuNum>> = row.collect_map(|tile| match tile {
+            Tile::Definite(num) => Some(num.clone()),
+            Tile::Indefinite(_) => None,
+        });
+        let definite = values.into_iter().flatten().collect::<Vec<_>>();
+        assert_eq!(definite, vec![SudokuNum::Four, SudokuNum::Two, SudokuNum::Eight]);
+
+        let givens: Vec<SudokuNum> = wfc.span(0..2, 1..2).collect_map(|tile| tile.as_definite().clone());
+        assert_eq!(givens, vec![SudokuNum::Four, SudokuNum::Two]);
+        let kinds: BTreeSet<bool> = row.collect_map(Tile::is_definite);
+        assert_eq!(kinds, BTreeSet::from([false, true]));
+    }
+
+    #[test]
+    fn rectangular_blocks() {
+        let solution = [
+            [1, 2, 3, 4, 5, 6],
+            [3, 4, 5, 6, 1, 2],
+            [5, 6, 1, 2, 3, 4],
+            [2, 1, 4, 3, 6, 5],
+            [4, 3, 6, 5, 2, 1],
+            [6, 5, 2, 1, 4, 3],
+        ];
+        // One blank per row, column and block, so each is decided by the others
+        let blanks = [0, 2, 4, 1, 3, 5];
+
+        let rules = SudokuRules::new(2, 3);
+        let states = rules.states();
+        assert_eq!(states.len(), 6);
+        let tiles = solution.iter()
+            .zip(blanks)
+            .flat_map(|(row, blank)| row.iter()
+                .enumerate()
+                .map(move |(x, digit)| match x == blank {
+                    true => None,
+                    false => Some(SudokuNum::try_from(*digit).unwrap()),
+                }))
+            .map(|digit| match digit {
+                Some(digit) => Tile::Definite(digit),
+                None => Tile::Indefinite(states.clone()),
+            })
+            .collect();
+
+        let mut wfc = rules.wfc(6, 6, tiles).unwrap();
+        assert_eq!(wfc.run(), Ok(()));
+        for (y, row) in solution.iter().enumerate() {
+            for (x, digit) in row.iter().enumerate() {
+                let tile = wfc.view_xy(x, y).get_at(x, y);
+                assert_eq!(u8::from(tile.as_definite().clone()), *digit);
+            }
+        }
+    }
+
+    #[test]
+    fn uneven_blocks() {
+        let rules = SudokuRules::new(2, 3);
+        let tiles = vec![Tile::Indefinite(rules.states()); 35];
+        assert_eq!(rules.wfc(7, 5, tiles).err(), Some(WfcError::InvalidDimensions));
+    }
+
+    /// Parses a sudoku written one row per line, with '.' for a blank cell
+    fn puzzle(puzzle: &str) -> Vec<Tile<SudokuNum>> {
+        puzzle.split_whitespace()
+            .flat_map(str::chars)
+            .map(|c| match c.to_digit(10) {
+                Some(digit) => Tile::Definite(SudokuNum::try_from(digit as u8).unwrap()),
+                None => Tile::Indefinite(SudokuNum::full_set()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn solve_logical() {
+        let tiles = puzzle("
+            ..3.5.7.9
+            4.67.....
+            .8.......
+            2..5....1
+            56..91...
+            .9...4.67
+            .45...9..
+            .7...2.4.
+            ...3.....
+        ");
+        let mut wfc = SudokuRules::default().wfc(9, 9, tiles).unwrap();
+        assert_eq!(wfc.solve_logical(), Ok(true));
+
+        let solution = puzzle("
+            123456789
+            456789123
+            789123456
+            234567891
+            567891234
+            891234567
+            345678912
+            678912345
+            912345678
+        ");
+        for (idx, tile) in solution.iter().enumerate() {
+            let (x, y) = (idx % 9, idx / 9);
+            assert_eq!(wfc.view(idx).get_at(x, y), tile);
+        }
+    }
+
+    #[test]
+    fn backtracking_stats() {
+        let easy = puzzle("
+            ..3.5.7.9
+            4.67.....
+            .8.......
+            2..5....1
+            56..91...
+            .9...4.67
+            .45...9..
+            .7...2.4.
+            ...3.....
+        ");
+        let blanks = easy.iter().filter(|tile| !tile.is_definite()).count();
+        let mut wfc = SudokuRules::default().wfc(9, 9, easy).unwrap();
+        assert_eq!(wfc.run_with_backtracking(), Ok(()));
+        assert_eq!(wfc.stats(), SolveStats { backtracks: 0, forced_moves: blanks, guesses: 0 });
+
+        let hard = puzzle("
+            8........
+            ..36.....
+            .7..9.2..
+            .5...7...
+            ....457..
+            ...1...3.
+            ..1....68
+            ..85...1.
+            .9....4..
+        ");
+        let mut wfc = SudokuRules::default().wfc(9, 9, hard).unwrap();
+        assert_eq!(wfc.run_with_backtracking(), Ok(()));
+        assert!(wfc.stats().guesses > 0, "{:?}", wfc.stats());
+
+        let solution = puzzle("
+            812753649
+            943682175
+            675491283
+            154237896
+            369845721
+            287169534
+            521974368
+            438526917
+            796318452
+        ");
+        for (idx, tile) in solution.iter().enumerate() {
+            assert_eq!(wfc.view(idx).get_at(idx % 9, idx / 9), tile);
+        }
+    }
+
+    #[test]
+    fn collapse_all_forced() {
+        let tiles = puzzle("
+            1234.6789
+            456789123
+            78912.456
+            234567891
+            5678912.4
+            891234567
+            3456789.2
+            678912345
+            .12345678
+        ");
+        let mut wfc = SudokuRules::default().wfc(9, 9, tiles).unwrap();
+        assert_eq!(wfc.collapse_all_forced(), Ok(5));
+        assert_eq!(wfc.indefinite_indices().count(), 0);
+        assert_eq!(wfc.collapse_all_forced(), Ok(0));
+
+        let tiles = vec![Tile::Indefinite(SudokuNum::full_set()); 81];
+        let mut wfc = SudokuRules::default().wfc(9, 9, tiles).unwrap();
+        assert_eq!(wfc.collapse_all_forced(), Ok(0));
+    }
+
+    #[test]
+    fn solve_logical_stalls() {
+        let tiles = vec![Tile::Indefinite(SudokuNum::full_set()); 81];
+        let mut wfc = SudokuRules::default().wfc(9, 9, tiles).unwrap();
+        assert_eq!(wfc.solve_logical(), Ok(false));
+    }
+
+    #[test]
+    fn with_givens() {
+        use SudokuNum::*;
+
+        let givens = [(0, One), (1, Two), (9, Four), (80, Nine)];
+        let wfc = Wfc::with_givens(9, 9, SudokuNum::full_set(), SudokuRules::default(), &givens).unwrap();
+        let candidates = |idx: usize| match wfc.view(idx).get_at(idx % 9, idx / 9) {
+            Tile::Indefinite(states) => states.clone(),
+            Tile::Definite(state) => panic!("{} should not be definite, found {:?}", idx, state),
+        };
+
+        // Shares a row and block with the first three givens
+        assert_eq!(candidates(2), BTreeSet::from([Three, Five, Six, Seven, Eight, Nine]));
+        // Shares a row with the first two and a column with the last
+        assert_eq!(candidates(8), BTreeSet::from([Three, Four, Five, Six, Seven, Eight]));
+        // Shares nothing with any given
+        assert_eq!(candidates(40), SudokuNum::full_set());
+
+        let clash = [(0, One), (5, One)];
+        let err = Wfc::with_givens(9, 9, SudokuNum::full_set(), SudokuRules::default(), &clash).unwrap_err();
+        assert_eq!(err, WfcError::InvalidGiven(1, Box::new(WfcError::Contradiction { idx: 5, x: 5, y: 0 })));
+    }
+}
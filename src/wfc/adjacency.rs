@@ -0,0 +1,517 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter, Write};
+use core::marker::PhantomData;
+
+use super::{BitSet, Direction, State, StateIndex, StateSet, Tile, WfcRules, WfcView};
+
+/// The errors that can occur while reading [AdjacencyRules] from text
+///
+/// Each variant carries the line number it occurred on, counting from 1
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParseError {
+    /// The line did not have exactly three fields
+    InvalidLine(usize),
+    /// The line named a tile missing from the name table
+    UnknownTile(usize, String),
+    /// The line used a direction other than N, E, S or W
+    UnknownDirection(usize, String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::InvalidLine(line) => write!(f, "line {} does not have exactly three fields", line),
+            ParseError::UnknownTile(line, name) => write!(f, "line {} names unknown tile {:?}", line, name),
+            ParseError::UnknownDirection(line, dir) => {
+                write!(f, "line {} uses unknown direction {:?}, expected N, E, S or W", line, dir)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// Rules that only allow a state next to the states it has been declared adjacent to
+#[derive(Debug, Clone)]
+pub struct AdjacencyRules<T: State> {
+    states: BTreeSet<T>,
+    allowed: BTreeMap<(T, Direction), BTreeSet<T>>,
+}
+
+impl<T: State> Default for AdjacencyRules<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: State> AdjacencyRules<T> {
+    /// Creates a rule set where nothing is allowed next to anything
+    pub fn new() -> Self {
+        Self {
+            states: BTreeSet::new(),
+            allowed: BTreeMap::new(),
+        }
+    }
+
+    /// Reads rules from lines of the form `A N B`, meaning tile B may be placed north of tile A
+    ///
+    /// Directions are one of N, E, S or W, and tile names are looked up in [names].
+    /// Blank lines and anything after a `#` are ignored
+    pub fn from_str(text: &str, names: &BTreeMap<String, T>) -> Result<Self, ParseError> {
+        let mut rules = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.split('#').next().unwrap_or_default();
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let (a, dir, b) = match fields[..] {
+                [] => continue,
+                [a, dir, b] => (a, dir, b),
+                _ => return Err(ParseError::InvalidLine(line_no)),
+            };
+
+            let tile = |name: &str| names.get(name)
+                .cloned()
+                .ok_or_else(|| ParseError::UnknownTile(line_no, name.to_string()));
+            let dir = match dir {
+                "N" => Direction::North,
+                "E" => Direction::East,
+                "S" => Direction::South,
+                "W" => Direction::West,
+                _ => return Err(ParseError::UnknownDirection(line_no, dir.to_string())),
+            };
+            rules.allow(tile(a)?, dir, tile(b)?);
+        }
+        Ok(rules)
+    }
+
+    /// Allows [b] to be placed in [dir] of [a], and therefore [a] in the opposite direction of [b]
+    pub fn allow(&mut self, a: T, dir: Direction, b: T) {
+        self.states.insert(a.clone());
+        self.states.insert(b.clone());
+        self.allowed.entry((b.clone(), dir.opposite()))
+            .or_default()
+            .insert(a.clone());
+        self.allowed.entry((a, dir))
+            .or_default()
+            .insert(b);
+    }
+
+    /// Returns the states that may be placed in [dir] of [state]
+    pub fn allowed(&self, state: &T, dir: Direction) -> Option<&BTreeSet<T>> {
+        self.allowed.get(&(state.clone(), dir))
+    }
+
+    /// Returns every state mentioned by these rules
+    pub fn states(&self) -> &BTreeSet<T> {
+        &self.states
+    }
+
+    /// Returns these rules as a graphviz DOT graph, with a node for each state named by [name]
+    /// and an edge from `a` to `b` labelled with the direction `b` may be placed in from `a`
+    ///
+    /// Every adjacency goes both ways, so each appears as two edges in opposite directions
+    pub fn to_dot(&self, name: impl Fn(&T) -> String) -> String {
+        // Names are quoted, so escape anything that would end the quotes early
+        let quoted = |state: &T| {
+            format!("\"{}\"", name(state).replace('\\', "\\\\").replace('"', "\\\""))
+        };
+        let mut dot = String::from("digraph rules {\n");
+        for state in &self.states {
+            writeln!(dot, "    {};", quoted(state)).unwrap();
+        }
+        for ((a, dir), bs) in &self.allowed {
+            for b in bs {
+                writeln!(dot, "    {} -> {} [label=\"{:?}\"];", quoted(a), quoted(b), dir).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns these rules extended with the three other quarter-turns of every entry
+    ///
+    /// [rotate] must turn a state a quarter-turn clockwise
+    pub fn with_rotations(&self, rotate: impl Fn(&T) -> T) -> AdjacencyRules<T> {
+        let mut rules = self.clone();
+        let mut rotated = self.clone();
+        for _ in 0..3 {
+            rotated = rotated.transformed(&rotate, Direction::clockwise);
+            rules.extend(&rotated);
+        }
+        rules
+    }
+
+    /// Returns these rules extended with the left-to-right mirror image of every entry
+    ///
+    /// [reflect] must mirror a state left-to-right
+    pub fn with_reflections(&self, reflect: impl Fn(&T) -> T) -> AdjacencyRules<T> {
+        let mut rules = self.clone();
+        rules.extend(&self.transformed(&reflect, Direction::mirrored));
+        rules
+    }
+
+    /// Returns a copy of these rules with every state mapped by [state] and direction by [dir]
+    fn transformed(&self, state: &impl Fn(&T) -> T, dir: impl Fn(Direction) -> Direction) -> Self {
+        let mut rules = Self::new();
+        rules.states = self.states.iter().map(state).collect();
+        for ((a, d), bs) in &self.allowed {
+            for b in bs {
+                rules.allow(state(a), dir(*d), state(b));
+            }
+        }
+        rules
+    }
+
+    /// Adds every state and adjacency in [other] to these rules
+    fn extend(&mut self, other: &Self) {
+        self.states.extend(other.states.iter().cloned());
+        for (key, bs) in &other.allowed {
+            self.allowed.entry(key.clone())
+                .or_default()
+                .extend(bs.iter().cloned());
+        }
+    }
+
+    /// Compiles these rules into bitmasks over each state's [StateIndex], so finding the states
+    /// of a tile takes a few bitwise operations instead of set lookups
+    ///
+    /// # Panics
+    /// * If [StateIndex::COUNT] > [BitSet::CAPACITY]
+    pub fn compile(&self) -> CompiledRules<T> where T: StateIndex {
+        assert!(T::COUNT <= BitSet::CAPACITY, "only states with up to 64 values can be compiled");
+        let mut allowed = vec![[BitSet::empty(); 4]; T::COUNT];
+        for ((state, dir), bs) in &self.allowed {
+            allowed[state.index()][*dir as usize] = BitSet::from_states(bs, T::index);
+        }
+        CompiledRules {
+            states: BitSet::from_states(&self.states, T::index),
+            allowed,
+            state: PhantomData,
+        }
+    }
+
+    /// Returns the states these rules allow at [map.pos()], for rules that build on these
+    pub(super) fn states_at<R: WfcRules<T>>(&self, map: &WfcView<'_, T, R>) -> BTreeSet<T> {
+        let mut possible = self.states.clone();
+        for dir in Direction::ALL {
+            // The neighbor sees this tile in the opposite direction
+            let allowed = match map.in_direction(dir) {
+                Some(Tile::Definite(state)) => self.allowed_any([state].into_iter(), dir.opposite()),
+                Some(Tile::Indefinite(states)) => self.allowed_any(states.iter(), dir.opposite()),
+                None => continue,
+            };
+            possible.intersect_with(&allowed);
+        }
+        possible
+    }
+
+    /// Returns the union of the states allowed in [dir] of any of [states]
+    fn allowed_any<'a, I: Iterator<Item=&'a T>>(&self, states: I, dir: Direction) -> BTreeSet<T>
+        where T: 'a {
+        states
+            .filter_map(|state| self.allowed(state, dir))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+impl<T: State> WfcRules<T> for AdjacencyRules<T> {
+    type Cache = ();
+
+    fn get_states(&self, map: WfcView<'_, T, Self>) -> BTreeSet<T> {
+        self.states_at(&map)
+    }
+
+    fn entropy(&self, tile: &Tile<T>) -> f64 {
+        match tile {
+            Tile::Definite(_) => 0.0,
+            Tile::Indefinite(states) => states.len() as f64,
+        }
+    }
+
+    fn dependents(&self, map: WfcView<'_, T, Self>) -> Vec<usize> {
+        map.neighbors()
+    }
+
+    /// Fails if any state has no neighbor it can be placed next to in some direction
+    fn validate(&self, all_states: &BTreeSet<T>) -> Result<(), String> {
+        for state in all_states {
+            for dir in Direction::ALL {
+                let allowed = self.allowed(state, dir)
+                    .map(|allowed| allowed.intersection(all_states).next().is_some())
+                    .unwrap_or(false);
+                if !allowed {
+                    return Err(format!("{:?} has no state that may be placed to its {:?}", state, dir));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [AdjacencyRules] compiled into bitmasks, see [AdjacencyRules::compile]
+#[derive(Debug, Clone)]
+pub struct CompiledRules<T: StateIndex> {
+    /// Every state mentioned by the rules
+    states: BitSet,
+    /// The states that may be placed in each [Direction] of each state, indexed by the state
+    allowed: Vec<[BitSet; 4]>,
+    state: PhantomData<T>,
+}
+
+impl<T: StateIndex> CompiledRules<T> {
+    /// Returns the set of the states in [tile]
+    fn mask(tile: &Tile<T>) -> BitSet {
+        match tile {
+            Tile::Definite(state) => BitSet::from_states([state], T::index),
+            Tile::Indefinite(states) => BitSet::from_states(states, T::index),
+        }
+    }
+
+    /// Returns the set of the states allowed in [dir] of any of the states in [mask]
+    fn allowed_any(&self, mask: BitSet, dir: Direction) -> BitSet {
+        let mut any = BitSet::empty();
+        for state in mask.iter() {
+            any.union_with(&self.allowed[state][dir as usize]);
+        }
+        any
+    }
+}
+
+impl<T: StateIndex> WfcRules<T> for CompiledRules<T> {
+    type Cache = ();
+
+    fn get_states(&self, map: WfcView<'_, T, Self>) -> BTreeSet<T> {
+        let mut possible = self.states;
+        for dir in Direction::ALL {
+            // The neighbor sees this tile in the opposite direction
+            if let Some(tile) = map.in_direction(dir) {
+                possible.intersect_with(&self.allowed_any(Self::mask(tile), dir.opposite()));
+            }
+        }
+        possible.to_states(T::from_index)
+    }
+
+    fn entropy(&self, tile: &Tile<T>) -> f64 {
+        match tile {
+            Tile::Definite(_) => 0.0,
+            Tile::Indefinite(states) => states.len() as f64,
+        }
+    }
+
+    fn dependents(&self, map: WfcView<'_, T, Self>) -> Vec<usize> {
+        map.neighbors()
+    }
+
+    /// Fails if any state has no neighbor it can be placed next to in some direction
+    fn validate(&self, all_states: &BTreeSet<T>) -> Result<(), String> {
+        let all = BitSet::from_states(all_states, T::index);
+        for state in all_states {
+            for dir in Direction::ALL {
+                let mut allowed = self.allowed[state.index()][dir as usize];
+                allowed.intersect_with(&all);
+                if allowed.is_empty() {
+                    return Err(format!("{:?} has no state that may be placed to its {:?}", state, dir));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::wfc::{AdjacencyRules, Direction, ParseError, State, StateIndex, Tile, Wfc, WfcError, WfcRules};
+
+    /// An L-shaped pipe, which in its first rotation connects north and east
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+    struct L(u8);
+
+    impl State for L {}
+
+    impl StateIndex for L {
+        const COUNT: usize = 4;
+
+        fn index(&self) -> usize {
+            self.0 as usize
+        }
+
+        fn from_index(i: usize) -> Self {
+            assert!(i < Self::COUNT, "L only has four rotations");
+            L(i as u8)
+        }
+    }
+
+    impl L {
+        fn rotate(&self) -> Self {
+            L((self.0 + 1) % 4)
+        }
+
+        fn reflect(&self) -> Self {
+            L(3 - self.0)
+        }
+    }
+
+    #[test]
+    fn allow_is_symmetric() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow(0, Direction::North, 1);
+        assert_eq!(rules.allowed(&0, Direction::North), Some(&BTreeSet::from([1])));
+        assert_eq!(rules.allowed(&1, Direction::South), Some(&BTreeSet::from([0])));
+        assert_eq!(rules.allowed(&0, Direction::South), None);
+        assert_eq!(rules.states(), &BTreeSet::from([0, 1]));
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow('g', Direction::East, 's');
+        rules.allow('s', Direction::South, 'w');
+        rules.allow('"', Direction::North, '"');
+        let dot = rules.to_dot(|state| state.to_string());
+
+        assert!(dot.starts_with("digraph rules {\n") && dot.ends_with("}\n"), "{}", dot);
+        for line in [
+            "    \"g\";",
+            "    \"w\";",
+            "    \"g\" -> \"s\" [label=\"East\"];",
+            "    \"s\" -> \"g\" [label=\"West\"];",
+            "    \"s\" -> \"w\" [label=\"South\"];",
+            "    \"w\" -> \"s\" [label=\"North\"];",
+            "    \"\\\"\" -> \"\\\"\" [label=\"South\"];",
+        ] {
+            assert!(dot.lines().any(|l| l == line), "missing {} in {}", line, dot);
+        }
+        assert_eq!(dot.matches("->").count(), 6);
+    }
+
+    #[test]
+    fn get_states() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow(0, Direction::East, 1);
+        rules.allow(1, Direction::East, 2);
+
+        let tiles = vec![
+            Tile::Definite(0),
+            Tile::Indefinite(BTreeSet::from([0, 1, 2])),
+            Tile::Indefinite(BTreeSet::from([0, 1, 2])),
+        ];
+        let wfc = Wfc::new(3, 1, tiles, rules);
+        assert_eq!(wfc.rules.get_states(wfc.view(1)), BTreeSet::from([1]));
+        assert_eq!(wfc.rules.get_states(wfc.view(2)), BTreeSet::from([1, 2]));
+    }
+
+    #[test]
+    fn with_rotations() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow(L(0), Direction::East, L(1));
+        let rules = rules.with_rotations(L::rotate);
+
+        assert_eq!(rules.states(), &BTreeSet::from([L(0), L(1), L(2), L(3)]));
+        assert_eq!(rules.allowed(&L(0), Direction::East), Some(&BTreeSet::from([L(1)])));
+        assert_eq!(rules.allowed(&L(1), Direction::South), Some(&BTreeSet::from([L(2)])));
+        assert_eq!(rules.allowed(&L(2), Direction::West), Some(&BTreeSet::from([L(3)])));
+        assert_eq!(rules.allowed(&L(3), Direction::North), Some(&BTreeSet::from([L(0)])));
+        assert_eq!(rules.allowed(&L(0), Direction::South), Some(&BTreeSet::from([L(3)])));
+    }
+
+    #[test]
+    fn with_reflections() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow(L(0), Direction::East, L(1));
+        let rules = rules.with_reflections(L::reflect);
+
+        assert_eq!(rules.allowed(&L(0), Direction::East), Some(&BTreeSet::from([L(1)])));
+        assert_eq!(rules.allowed(&L(3), Direction::West), Some(&BTreeSet::from([L(2)])));
+        assert_eq!(rules.allowed(&L(2), Direction::East), Some(&BTreeSet::from([L(3)])));
+    }
+
+    #[test]
+    fn compile() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow(L(0), Direction::East, L(1));
+        rules.allow(L(0), Direction::East, L(0));
+        rules.allow(L(0), Direction::South, L(2));
+        let rules = rules.with_rotations(L::rotate);
+        let compiled = rules.compile();
+        let all = BTreeSet::from([L(0), L(1), L(2), L(3)]);
+        assert_eq!(compiled.validate(&all), rules.validate(&all));
+
+        for seed in 0..8 {
+            let tiles = vec![Tile::Indefinite(all.clone()); 36];
+            let mut plain = Wfc::new(6, 6, tiles.clone(), rules.clone());
+            let mut fast = Wfc::new(6, 6, tiles, compiled.clone());
+            let (mut plain_rng, mut fast_rng) = (StdRng::seed_from_u64(seed), StdRng::seed_from_u64(seed));
+            loop {
+                let result = plain.try_step_with(&mut plain_rng);
+                assert_eq!(fast.try_step_with(&mut fast_rng), result);
+                assert_eq!(fast.tiles(), plain.tiles());
+                if result != Ok(true) {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn validate_isolated() {
+        let mut rules = AdjacencyRules::new();
+        for dir in Direction::ALL {
+            rules.allow(0, dir, 1);
+            rules.allow(1, dir, 0);
+        }
+        assert_eq!(rules.validate(&BTreeSet::from([0, 1])), Ok(()));
+
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 4];
+        let mut wfc = Wfc::new(2, 2, tiles, rules);
+        assert_eq!(wfc.run(), Err(WfcError::InvalidRules("2 has no state that may be placed to its North".into())));
+    }
+
+    #[test]
+    fn from_str() {
+        let names = BTreeMap::from([
+            ("grass".to_string(), 0),
+            ("sand".to_string(), 1),
+            ("water".to_string(), 2),
+        ]);
+        let text = "
+            # A beach
+            grass E sand
+            sand E water  # shallows
+            water S water
+            grass N grass
+        ";
+        let rules = AdjacencyRules::from_str(text, &names).unwrap();
+        assert_eq!(rules.allowed(&0, Direction::East), Some(&BTreeSet::from([1])));
+        assert_eq!(rules.allowed(&1, Direction::East), Some(&BTreeSet::from([2])));
+        assert_eq!(rules.allowed(&1, Direction::West), Some(&BTreeSet::from([0])));
+        assert_eq!(rules.allowed(&2, Direction::North), Some(&BTreeSet::from([2])));
+        assert_eq!(rules.allowed(&0, Direction::South), Some(&BTreeSet::from([0])));
+        assert_eq!(rules.states(), &BTreeSet::from([0, 1, 2]));
+
+        assert_eq!(AdjacencyRules::from_str("grass E", &names).unwrap_err(), ParseError::InvalidLine(1));
+        assert_eq!(AdjacencyRules::from_str("\ngrass E lava", &names).unwrap_err(),
+                   ParseError::UnknownTile(2, "lava".into()));
+        assert_eq!(AdjacencyRules::from_str("grass Up sand", &names).unwrap_err(),
+                   ParseError::UnknownDirection(1, "Up".into()));
+    }
+
+    #[test]
+    fn parse_error_display() {
+        assert_eq!(ParseError::InvalidLine(3).to_string(), "line 3 does not have exactly three fields");
+        assert_eq!(ParseError::UnknownTile(2, "lava".into()).to_string(), "line 2 names unknown tile \"lava\"");
+        let err: Box<dyn std::error::Error> = Box::new(ParseError::UnknownDirection(1, "Up".into()));
+        assert_eq!(err.to_string(), "line 1 uses unknown direction \"Up\", expected N, E, S or W");
+    }
+}
@@ -0,0 +1,35 @@
+//! The phases of [Wfc::step], exposed by the `bench` feature so the benchmarks under `benches/`
+//! can time each of them
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use rand::Rng;
+
+use super::{State, Wfc, WfcError, WfcRules};
+
+/// The entropy phase, returning every indefinite tile tied for the lowest entropy
+pub fn lowest_entropy_tiles<T: State, R: WfcRules<T>, G: Rng + ?Sized>(
+    wfc: &Wfc<T, R>,
+    rng: &mut G,
+) -> Result<Vec<usize>, WfcError> {
+    wfc.lowest_entropy_tiles(rng)
+}
+
+/// The selection phase, picking one of the [tied] tiles
+pub fn break_tie<T: State, R: WfcRules<T>, G: Rng + ?Sized>(
+    wfc: &Wfc<T, R>,
+    tied: &[usize],
+    rng: &mut G,
+) -> Option<usize> {
+    wfc.break_tie(tied, rng)
+}
+
+/// The collapse phase, collapsing the tile at [idx] without propagating
+pub fn collapse<T: State, R: WfcRules<T>, G: Rng + ?Sized>(
+    wfc: &mut Wfc<T, R>,
+    idx: usize,
+    rng: &mut G,
+) -> Result<BTreeSet<T>, WfcError> {
+    wfc.collapse(idx, rng)
+}
@@ -0,0 +1,169 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{SelectionStrategy, State, TieBreak, Tile, Topology, Wfc, WfcError, WfcRules};
+
+/// Collects the options for a [Wfc] so they can be given by name, see [WfcBuilder::new]
+///
+/// Only the rules and the tiles have to be given, everything else defaults to how [Wfc::new]
+/// leaves it
+#[derive(Debug)]
+pub struct WfcBuilder<T: State, R: WfcRules<T>> {
+    width: usize,
+    height: usize,
+    rules: Option<R>,
+    tiles: Option<Vec<Tile<T>>>,
+    seed: Option<u64>,
+    wrapping: bool,
+    topology: Topology,
+    jitter: f64,
+    tie_break: TieBreak,
+    selection: Option<Box<dyn SelectionStrategy<T, R>>>,
+}
+
+impl<T: State, R: WfcRules<T>> WfcBuilder<T, R> {
+    /// Starts building a [width]x[height] map
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            rules: None,
+            tiles: None,
+            seed: None,
+            wrapping: false,
+            topology: Topology::default(),
+            jitter: 0.0,
+            tie_break: TieBreak::default(),
+            selection: None,
+        }
+    }
+
+    /// Sets the rules of the map
+    pub fn rules(mut self, rules: R) -> Self {
+        self.rules = Some(rules);
+        self
+    }
+
+    /// Starts the map from [tiles], which must have width * height tiles
+    pub fn tiles(mut self, tiles: Vec<Tile<T>>) -> Self {
+        self.tiles = Some(tiles);
+        self
+    }
+
+    /// Starts every tile of the map as any of [all_states]
+    pub fn uniform(mut self, all_states: BTreeSet<T>) -> Self {
+        self.tiles = Some(vec![Tile::Indefinite(all_states); self.width * self.height]);
+        self
+    }
+
+    /// Seeds the entropy noise so ties are always broken the same way, see [Wfc::with_noise_seed]
+    #[cfg(feature = "std")]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// See [Wfc::with_wrapping]
+    pub fn wrapping(mut self, wrapping: bool) -> Self {
+        self.wrapping = wrapping;
+        self
+    }
+
+    /// See [Wfc::with_topology]
+    pub fn topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// See [Wfc::with_jitter]
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// See [Wfc::with_tie_break]
+    pub fn tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// See [Wfc::with_selection]
+    pub fn selection(mut self, selection: impl SelectionStrategy<T, R> + 'static) -> Self {
+        self.selection = Some(Box::new(selection));
+        self
+    }
+
+    /// Creates the map
+    ///
+    /// Returns [WfcError::InvalidRules] if no rules were given, and [WfcError::InvalidDimensions]
+    /// if no tiles were given or they do not fit the map
+    pub fn build(self) -> Result<Wfc<T, R>, WfcError> {
+        let rules = self.rules.ok_or_else(|| WfcError::InvalidRules(String::from("no rules were given")))?;
+        let tiles = self.tiles.ok_or(WfcError::InvalidDimensions)?;
+        let mut wfc = Wfc::try_new(self.width, self.height, tiles, rules)?
+            .with_wrapping(self.wrapping)
+            .with_topology(self.topology)
+            .with_jitter(self.jitter)
+            .with_tie_break(self.tie_break);
+        wfc.selection = self.selection;
+        #[cfg(feature = "std")]
+        if let Some(seed) = self.seed {
+            wfc = wfc.with_noise_seed(seed);
+        }
+        Ok(wfc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::wfc::{ScanlineSelection, Tile, Topology, WfcBuilder, WfcError, WfcRules, WfcView};
+
+    #[derive(Debug)]
+    struct S;
+
+    impl WfcRules<i32> for S {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0, 1])
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let mut wfc = WfcBuilder::new(4, 3)
+            .seed(5)
+            .wrapping(true)
+            .topology(Topology::HexFlat)
+            .selection(ScanlineSelection)
+            .rules(S)
+            .uniform(BTreeSet::from([0, 1]))
+            .build()
+            .unwrap();
+        assert_eq!((wfc.width(), wfc.height(), wfc.len()), (4, 3, 12));
+        assert!(wfc.is_wrapping());
+        assert_eq!(wfc.topology(), Topology::HexFlat);
+        assert_eq!(wfc.neighbors_of(0), vec![8, 9, 1, 4, 3, 11]);
+        assert!(wfc.entropy_ranking().iter().all(|(_, entropy)| *entropy > 0.0));
+        assert_eq!(wfc.try_step(), Ok(true));
+        assert!(wfc[0].is_definite());
+
+        let plain = WfcBuilder::new(2, 2).rules(S).tiles(vec![Tile::Definite(0); 4]).build().unwrap();
+        assert!(!plain.is_wrapping() && plain.topology() == Topology::Square);
+        assert_eq!(plain.entropy_ranking(), vec![]);
+    }
+
+    #[test]
+    fn builder_missing() {
+        let no_rules = WfcBuilder::<i32, S>::new(2, 2).uniform(BTreeSet::from([0])).build();
+        assert!(matches!(no_rules, Err(WfcError::InvalidRules(_))));
+        assert!(matches!(WfcBuilder::new(2, 2).rules(S).build(), Err(WfcError::InvalidDimensions)));
+        let wrong_size = WfcBuilder::new(2, 2).rules(S).tiles(vec![Tile::Definite(0); 3]).build();
+        assert!(matches!(wrong_size, Err(WfcError::InvalidDimensions)));
+    }
+}
@@ -0,0 +1,153 @@
+//! Generating an endless map one square chunk at a time
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use rand::Rng;
+#[cfg(feature = "std")]
+use rand::SeedableRng;
+#[cfg(feature = "std")]
+use rand::rngs::StdRng;
+
+use super::{Direction, State, Tile, Wfc, WfcError, WfcRules};
+
+/// The states a chunk's tiles may be, and the tiles along the edges of its neighboring chunks
+/// that have already been generated
+#[derive(Debug, Clone)]
+pub struct BorderConstraints<T: State> {
+    pub states: BTreeSet<T>,
+    /// The tiles of each neighbor's edge that touches the chunk, ordered as in [Direction::ALL]
+    /// and then left-to-right or top-to-bottom
+    pub edges: [Option<Vec<T>>; 4],
+}
+
+impl<T: State> BorderConstraints<T> {
+    /// Creates the constraints for a chunk with no generated neighbors, whose tiles may be any
+    /// of [states]
+    pub fn new(states: BTreeSet<T>) -> Self {
+        Self { states, edges: [None, None, None, None] }
+    }
+
+    /// Constrains the chunk by the generated [chunk] next to it in [dir], as returned by
+    /// [Wfc::generate_chunk]
+    ///
+    /// # Panics
+    /// * If [chunk] is not square
+    /// * If any tile on the edge of [chunk] facing this one is indefinite
+    pub fn with_neighbor(mut self, dir: Direction, chunk: &[Tile<T>]) -> Self {
+        let size = chunk.len().isqrt();
+        assert_eq!(size * size, chunk.len(), "chunk must be square");
+
+        let edge = (0..size)
+            .map(|i| match dir {
+                Direction::North => (i, size - 1),
+                Direction::East => (0, i),
+                Direction::South => (i, 0),
+                Direction::West => (size - 1, i),
+            })
+            .map(|(x, y)| chunk[y * size + x].as_definite().clone())
+            .collect();
+        self.edges[dir as usize] = Some(edge);
+        self
+    }
+}
+
+impl<T: State, R: WfcRules<T>> Wfc<T, R> {
+    /// Generates the [chunk_size]x[chunk_size] chunk at [chunk_x], [chunk_y] of an endless map
+    /// so that it fits against the neighboring edges in [border], returning its tiles
+    ///
+    /// The map must be one tile larger than the chunk on every side. That ring is set to the
+    /// neighbors' edges, so the rules see them as they would inside one large map. The rng is
+    /// seeded from the chunk's position, so the same chunk and border always give the same tiles
+    ///
+    /// # Panics
+    /// * If the map is not [chunk_size] + 2 tiles wide and tall
+    /// * If an edge in [border] is not [chunk_size] tiles long
+    #[cfg(feature = "std")]
+    pub fn generate_chunk(
+        &mut self,
+        chunk_x: i64,
+        chunk_y: i64,
+        chunk_size: usize,
+        border: &BorderConstraints<T>,
+    ) -> Result<Vec<Tile<T>>, WfcError> {
+        let seed = (chunk_x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ chunk_y as u64;
+        self.generate_chunk_with(chunk_size, border, &mut StdRng::seed_from_u64(seed))
+    }
+
+    /// Like [generate_chunk], but makes every random choice with [rng]
+    pub fn generate_chunk_with<G: Rng + ?Sized>(
+        &mut self,
+        chunk_size: usize,
+        border: &BorderConstraints<T>,
+        rng: &mut G,
+    ) -> Result<Vec<Tile<T>>, WfcError> {
+        let size = chunk_size + 2;
+        assert!(
+            self.width == size && self.height == size,
+            "the map must be one tile larger than the chunk on every side"
+        );
+
+        self.reset_uniform(border.states.clone());
+        for (dir, edge) in Direction::ALL.into_iter().zip(&border.edges) {
+            let edge = match edge {
+                Some(edge) => edge,
+                None => continue,
+            };
+            assert_eq!(edge.len(), chunk_size, "edges must be as long as the chunk");
+            for (i, state) in edge.iter().enumerate() {
+                let (x, y) = match dir {
+                    Direction::North => (i + 1, 0),
+                    Direction::East => (size - 1, i + 1),
+                    Direction::South => (i + 1, size - 1),
+                    Direction::West => (0, i + 1),
+                };
+                self.set(self.xy_pair(x, y), Tile::Definite(state.clone()));
+            }
+        }
+
+        self.run_with_backtracking_with(rng)?;
+        Ok((1..=chunk_size)
+            .flat_map(|y| self.map[y * size + 1..y * size + 1 + chunk_size].iter().cloned())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::wfc::{AdjacencyRules, Direction, Tile, Wfc};
+
+    use super::BorderConstraints;
+
+    /// Rules for horizontal stripes, where a tile's east and west neighbors match it
+    fn stripes() -> AdjacencyRules<i32> {
+        let mut rules = AdjacencyRules::new();
+        for state in 0..3 {
+            rules.allow(state, Direction::East, state);
+            for below in 0..3 {
+                rules.allow(state, Direction::South, below);
+            }
+        }
+        rules
+    }
+
+    #[test]
+    fn chunks() {
+        let states = BTreeSet::from([0, 1, 2]);
+        let tiles = vec![Tile::Indefinite(states.clone()); 36];
+        let mut wfc = Wfc::new(6, 6, tiles, stripes());
+
+        let border = BorderConstraints::new(states.clone());
+        let left = wfc.generate_chunk(0, 0, 4, &border).unwrap();
+        assert_eq!(wfc.generate_chunk(0, 0, 4, &border), Ok(left.clone()));
+
+        let border = BorderConstraints::new(states).with_neighbor(Direction::West, &left);
+        let right = wfc.generate_chunk(1, 0, 4, &border).unwrap();
+        assert_eq!(right.len(), 16);
+        for y in 0..4 {
+            assert_eq!(left[y * 4 + 3], right[y * 4]);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use super::{MaybeSync, State, Tile, WfcRules, WfcView};
+
+/// One of the rules combined by [CompositeRules]
+///
+/// Unlike [WfcRules] this can be boxed, so rules of different types can be combined
+pub trait SubRules<T: State>: MaybeSync {
+    /// Returns the valid states that are possible in [map.pos()]
+    fn get_states(&self, map: &WfcView<'_, T, CompositeRules<T>>) -> BTreeSet<T>;
+
+    fn entropy(&self, _tile: &Tile<T>) -> f64 {
+        0.0
+    }
+}
+
+/// Rules that only allow the states every one of its sub-rules allows
+pub struct CompositeRules<T: State>(pub Vec<Box<dyn SubRules<T>>>);
+
+impl<T: State> WfcRules<T> for CompositeRules<T> {
+    type Cache = ();
+
+    fn get_states(&self, map: WfcView<'_, T, Self>) -> BTreeSet<T> {
+        let mut rules = self.0.iter();
+        let mut possible = match rules.next() {
+            Some(rule) => rule.get_states(&map),
+            None => return BTreeSet::new(),
+        };
+        for rule in rules {
+            let states = rule.get_states(&map);
+            possible.retain(|state| states.contains(state));
+        }
+        possible
+    }
+
+    fn entropy(&self, tile: &Tile<T>) -> f64 {
+        self.0.iter()
+            .map(|rule| rule.entropy(tile))
+            .min_by(f64::total_cmp)
+            .unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::wfc::{CompositeRules, SubRules, Tile, Wfc, WfcRules, WfcView};
+
+    struct Fixed(BTreeSet<i32>, f64);
+
+    impl SubRules<i32> for Fixed {
+        fn get_states(&self, _: &WfcView<'_, i32, CompositeRules<i32>>) -> BTreeSet<i32> {
+            self.0.clone()
+        }
+
+        fn entropy(&self, _tile: &Tile<i32>) -> f64 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn intersection() {
+        let rules = CompositeRules(vec![
+            Box::new(Fixed(BTreeSet::from([0, 1, 2]), 2.0)),
+            Box::new(Fixed(BTreeSet::from([1, 2, 3]), 1.0)),
+        ]);
+        let wfc = Wfc::new(1, 1, vec![Tile::Indefinite(BTreeSet::from([0, 1, 2, 3]))], rules);
+        assert_eq!(wfc.rules.get_states(wfc.view(0)), BTreeSet::from([1, 2]));
+        assert_eq!(wfc.rules.entropy(&wfc.map[0]), 1.0);
+    }
+}
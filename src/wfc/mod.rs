@@ -1,18 +1,61 @@
 #![allow(dead_code)]
 
-use std::collections::BTreeSet;
-use std::fmt::Debug;
-use std::mem::replace;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Display, Formatter};
+use core::mem::{replace, take};
+use core::ops::{Index, Range};
 
-use rand::{Rng, thread_rng};
-use rand::prelude::SliceRandom;
+use rand::Rng;
+use rand::seq::{IteratorRandom, SliceRandom};
+#[cfg(feature = "std")]
+use rand::{SeedableRng, thread_rng};
+#[cfg(feature = "std")]
+use rand::rngs::StdRng;
 
+pub use adjacency::*;
+pub use builder::*;
+pub use composite::*;
+pub use selection::*;
+pub use state_set::*;
 pub use view::*;
 
+mod adjacency;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod builder;
+pub mod chunk;
+mod composite;
+pub mod overlapping;
+pub mod prelude;
+pub mod rules;
+mod selection;
+mod state_set;
+pub mod three_d;
 mod view;
 
-pub trait State: Clone + PartialOrd + Ord {}
+/// Requires [Send] and [Sync] when the `parallel` feature is enabled, so states and rules can be
+/// shared with the propagation threads
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Send + Sync {}
+#[cfg(feature = "parallel")]
+impl<T: Send + Sync> MaybeSync for T {}
+
+/// Requires [Send] and [Sync] when the `parallel` feature is enabled, so states and rules can be
+/// shared with the propagation threads
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "parallel"))]
+impl<T> MaybeSync for T {}
+
+pub trait State: Clone + Debug + PartialOrd + Ord + MaybeSync {}
 impl State for i32 {}
+impl State for u8 {}
+impl State for char {}
 // impl<T: State + PartialOrd + Ord> State for T {}
 
 /// The generic tile class for the WFC algorithm
@@ -22,14 +65,115 @@ pub enum Tile<T: State> {
     Indefinite(BTreeSet<T>),
 }
 
+/// The fewest tiles a propagation batch needs before the `parallel` feature splits it between
+/// threads, see [Wfc::with_parallel_min_batch]
+#[cfg(feature = "parallel")]
+pub const PARALLEL_MIN_BATCH: usize = 256;
+
+/// The errors that can occur while building or running a WFC
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WfcError {
+    /// The width or height was zero, or the tiles did not fill the map
+    InvalidDimensions,
+    /// The row at the index was a different length to the rows before it
+    RaggedRow(usize),
+    /// The tile at the index was expected to be indefinite
+    AlreadyDefinite(usize),
+    /// The tile at [idx], which is at [x], [y], was left with no possible states
+    Contradiction { idx: usize, x: usize, y: usize },
+    /// The tile at [idx] of a [three_d::Wfc3], which is at [x], [y], [z], was left with no
+    /// possible states
+    Contradiction3 { idx: usize, x: usize, y: usize, z: usize },
+    /// The tile at the index was expected to be definite
+    NotCollapsed(usize),
+    /// The rules gave the tile at the index an entropy of NaN
+    InvalidEntropy(usize),
+    /// The map was still not definite after the maximum number of steps
+    StepLimit,
+    /// The rules failed [WfcRules::validate] with the given reason
+    InvalidRules(String),
+    /// The given at the index into the givens could not be placed, for the wrapped reason
+    InvalidGiven(usize, Box<WfcError>),
+    /// The tile at the index was frozen with [Wfc::freeze] and cannot be changed
+    Frozen(usize),
+}
+
+impl Display for WfcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            WfcError::InvalidDimensions => write!(f, "the map must be non-empty and filled by its tiles"),
+            WfcError::RaggedRow(row) => write!(f, "row {} is a different length to the rows before it", row),
+            WfcError::AlreadyDefinite(idx) => write!(f, "tile {} is already definite", idx),
+            WfcError::Contradiction { idx, x, y } => {
+                write!(f, "tile {} at ({}, {}) was left with no possible states", idx, x, y)
+            }
+            WfcError::Contradiction3 { idx, x, y, z } => {
+                write!(f, "tile {} at ({}, {}, {}) was left with no possible states", idx, x, y, z)
+            }
+            WfcError::NotCollapsed(idx) => write!(f, "tile {} is not definite", idx),
+            WfcError::InvalidEntropy(idx) => write!(f, "the rules gave tile {} an entropy of NaN", idx),
+            WfcError::StepLimit => write!(f, "the map was not definite within the step limit"),
+            WfcError::InvalidRules(reason) => write!(f, "invalid rules: {}", reason),
+            WfcError::InvalidGiven(idx, err) => write!(f, "given {} could not be placed: {}", idx, err),
+            WfcError::Frozen(idx) => write!(f, "tile {} is frozen", idx),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WfcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WfcError::InvalidGiven(_, err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 /// A controller for dictating rules of the WFC algorithm
-pub trait WfcRules<T: State>: Sized {
+pub trait WfcRules<T: State>: Sized + MaybeSync {
+    /// Memory the rules can keep between calls to [get_states_cached], one per map.
+    /// Rules without any use `()`
+    type Cache: Default + Debug + MaybeSync;
+
     /// Returns the valid states that are possible in [map.pos()]
     fn get_states(&self, map: WfcView<'_, T, Self>) -> BTreeSet<T>;
 
+    /// Like [get_states], but lets the rules remember earlier results in [cache]
+    ///
+    /// This is what the map calls, and defaults to ignoring the cache
+    fn get_states_cached(&self, map: WfcView<'_, T, Self>, _cache: &mut Self::Cache) -> BTreeSet<T> {
+        self.get_states(map)
+    }
+
     fn entropy(&self, _tile: &Tile<T>) -> f64 {
         0.0
     }
+
+    /// Returns the entropy of the tile at [map.pos()], for rules that rank tiles by their
+    /// surroundings too. This is what the map calls, and defaults to [entropy] of that tile
+    fn entropy_at(&self, map: WfcView<'_, T, Self>) -> f64 {
+        let (x, y) = *map.pos();
+        self.entropy(&map.wfc[map.wfc.xy_pair(x, y)])
+    }
+
+    /// Returns the indices of the tiles whose states may depend on the tile at [map.pos()]
+    ///
+    /// Only these are re-derived when that tile changes. Defaults to every tile in the map
+    fn dependents(&self, map: WfcView<'_, T, Self>) -> Vec<usize> {
+        (0..map.width() * map.height()).collect()
+    }
+
+    /// Returns how likely [state] is to be chosen when a tile collapses, relative to the tile's
+    /// other states. Defaults to every state being equally likely
+    fn state_weight(&self, _state: &T) -> f64 {
+        1.0
+    }
+
+    /// Checks the rules make sense for [all_states] before generation starts
+    fn validate(&self, _all_states: &BTreeSet<T>) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 /// The main structure for the WFC algorithm
@@ -39,21 +183,200 @@ pub struct Wfc<T: State, R: WfcRules<T>> {
     height: usize,
     rules: R,
     map: Vec<Tile<T>>,
+    jitter: f64,
+    tie_break: TieBreak,
+    noise: Vec<f64>,
+    propagation_radius: Option<usize>,
+    selection: Option<Box<dyn SelectionStrategy<T, R>>>,
+    record: Vec<(usize, T)>,
+    stats: SolveStats,
+    cache: R::Cache,
+    indefinite_count: usize,
+    topology: Topology,
+    wrapping: bool,
+    frozen: Vec<bool>,
+    /// Which tiles are waiting to be re-derived, kept between propagations to save reallocating
+    queued: Vec<bool>,
+    #[cfg(feature = "parallel")]
+    parallel_min_batch: usize,
 }
 
 impl<T: State, R: WfcRules<T>> Wfc<T, R> {
     /// Creates a new WFC using
+    ///
+    /// # Panics
+    /// * If [width] or [height] is zero
+    /// * If [tiles].len() != width * height
     pub fn new(width: usize, height: usize, tiles: Vec<Tile<T>>, rules: R) -> Self {
-        assert!(width > 0);
-        assert!(height > 0);
-        assert_eq!(tiles.len(), width * height, "Tiles.len() must be w*h");
+        Self::try_new(width, height, tiles, rules)
+            .expect("width & height must be non-zero and tiles.len() must be w*h")
+    }
+
+    /// Creates a new WFC where every tile may be any value of [T], see [FullStates]
+    ///
+    /// # Panics
+    /// * If [width] or [height] is zero
+    pub fn new_full(width: usize, height: usize, rules: R) -> Self where T: FullStates {
+        Self::new(width, height, vec![Tile::Indefinite(T::all()); width * height], rules)
+    }
 
-        Self {
+    /// Creates a new WFC, returning [WfcError::InvalidDimensions] instead of panicking
+    pub fn try_new(width: usize, height: usize, tiles: Vec<Tile<T>>, rules: R) -> Result<Self, WfcError> {
+        if width == 0 || height == 0 || tiles.len() != width * height {
+            return Err(WfcError::InvalidDimensions);
+        }
+
+        Ok(Self {
+            indefinite_count: tiles.iter().filter(|tile| !tile.is_definite()).count(),
             map: tiles,
             width,
             height,
             rules,
+            jitter: 0.0,
+            tie_break: TieBreak::Random,
+            noise: Vec::new(),
+            propagation_radius: None,
+            selection: None,
+            record: Vec::new(),
+            stats: SolveStats::default(),
+            cache: R::Cache::default(),
+            topology: Topology::Square,
+            wrapping: false,
+            frozen: vec![false; width * height],
+            queued: vec![false; width * height],
+            #[cfg(feature = "parallel")]
+            parallel_min_batch: PARALLEL_MIN_BATCH,
+        })
+    }
+
+    /// Creates a new WFC from a list of rows, taking the width from the length of the rows
+    ///
+    /// Returns [WfcError::RaggedRow] if a row is a different length to the first
+    pub fn from_grid(grid: Vec<Vec<Tile<T>>>, rules: R) -> Result<Self, WfcError> {
+        let height = grid.len();
+        let width = grid.first().map(Vec::len).unwrap_or(0);
+        if let Some(row) = grid.iter().position(|row| row.len() != width) {
+            return Err(WfcError::RaggedRow(row));
+        }
+        Self::try_new(width, height, grid.into_iter().flatten().collect(), rules)
+    }
+
+    /// Creates a new WFC where every tile may be any of [all_states], then places each of
+    /// [givens] in order with [Wfc::collapse_at], propagating after each one
+    ///
+    /// Returns [WfcError::InvalidGiven] naming the first given that could not be placed
+    pub fn with_givens(
+        width: usize,
+        height: usize,
+        all_states: BTreeSet<T>,
+        rules: R,
+        givens: &[(usize, T)],
+    ) -> Result<Self, WfcError> {
+        let tiles = vec![Tile::Indefinite(all_states); width * height];
+        let mut wfc = Self::try_new(width, height, tiles, rules)?;
+        for (i, (idx, state)) in givens.iter().enumerate() {
+            if *idx >= wfc.map.len() {
+                return Err(WfcError::InvalidGiven(i, Box::new(WfcError::InvalidDimensions)));
+            }
+            wfc.collapse_at(*idx, state.clone())
+                .map_err(|err| WfcError::InvalidGiven(i, Box::new(err)))?;
         }
+        Ok(wfc)
+    }
+
+    /// Creates a new WFC where every tile may be any of [all_states], then collapses roughly
+    /// [density] of the tiles to random states, propagating after each one
+    ///
+    /// [density] is clamped to [0, 1], and the same [seed] always picks the same tiles and states.
+    /// Tiles that would cause a contradiction are left indefinite
+    #[cfg(feature = "std")]
+    pub fn new_seeded_density(
+        width: usize,
+        height: usize,
+        all_states: BTreeSet<T>,
+        rules: R,
+        seed: u64,
+        density: f64,
+    ) -> Result<Self, WfcError> {
+        let tiles = vec![Tile::Indefinite(all_states); width * height];
+        let mut wfc = Self::try_new(width, height, tiles, rules)?;
+        let density = density.clamp(0.0, 1.0);
+        let mut rng = StdRng::seed_from_u64(seed);
+        for idx in 0..wfc.map.len() {
+            if rng.gen::<f64>() < density && !wfc.map[idx].is_definite() {
+                // A contradiction leaves the tile indefinite, which is fine for a seed
+                let _ = wfc.step_at_with(idx, &mut rng);
+            }
+        }
+        Ok(wfc)
+    }
+
+    /// Adds a fixed amount of noise below [ENTROPY_NOISE] to each tile's entropy, drawn once from
+    /// [seed], so tiles of equal entropy are always picked in the same order for the same seed
+    #[cfg(feature = "std")]
+    pub fn with_noise_seed(mut self, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.noise = (0..self.map.len())
+            .map(|_| rng.gen::<f64>() * ENTROPY_NOISE)
+            .collect();
+        self
+    }
+
+    /// Stops propagation after [radius] rounds of re-deriving dependents, or never if None
+    ///
+    /// With the neighbor-based dependents of [AdjacencyRules] a radius of 1 only updates the
+    /// tiles next to a change. This bounds the cost of each step, at the risk of leaving tiles
+    /// further away with states that can no longer be placed
+    pub fn with_propagation_radius(mut self, radius: Option<usize>) -> Self {
+        self.propagation_radius = radius;
+        self
+    }
+
+    /// Picks the next tile to collapse with [selection] instead of by lowest entropy
+    pub fn with_selection(mut self, selection: impl SelectionStrategy<T, R> + 'static) -> Self {
+        self.selection = Some(Box::new(selection));
+        self
+    }
+
+    /// Adds up to [jitter] of random noise to each tile's entropy when picking the next tile,
+    /// so tiles of equal entropy are chosen between fairly
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Picks between the tiles tied for the lowest entropy by [tie_break] instead of randomly
+    ///
+    /// Has no effect on a [SelectionStrategy] given to [with_selection]
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// Returns how tiles tied for the lowest entropy are picked between
+    pub fn tie_break(&self) -> TieBreak {
+        self.tie_break
+    }
+
+    /// Only derives batches of at least [min_batch] tiles on multiple threads during propagation,
+    /// since starting the threads costs more than deriving a few tiles. Defaults to
+    /// [PARALLEL_MIN_BATCH]
+    #[cfg(feature = "parallel")]
+    pub fn with_parallel_min_batch(mut self, min_batch: usize) -> Self {
+        self.parallel_min_batch = min_batch;
+        self
+    }
+
+    /// Lays the tiles out as [topology] instead of as squares, changing their neighbors
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Makes tiles on opposite edges of the map neighbors when [wrapping], so it tiles seamlessly
+    pub fn with_wrapping(mut self, wrapping: bool) -> Self {
+        self.wrapping = wrapping;
+        self
     }
 
     /// Returns the width of the map
@@ -68,8 +391,73 @@ impl<T: State, R: WfcRules<T>> Wfc<T, R> {
         self.height
     }
 
+    /// Returns the number of tiles in the map
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns whether the map has no tiles, which the constructors never allow
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the number of indefinite tiles, which is kept up to date as the map changes
+    #[inline(always)]
+    pub fn indefinite_count(&self) -> usize {
+        self.indefinite_count
+    }
+
+    /// Returns the fraction of tiles that are definite, from 0 to 1
+    pub fn progress(&self) -> f64 {
+        1.0 - self.indefinite_count as f64 / self.map.len() as f64
+    }
+
+    /// Returns how many tiles have each number of possible states, counting a definite tile as
+    /// having 1
+    pub fn candidate_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for tile in &self.map {
+            let count = match tile {
+                Tile::Definite(_) => 1,
+                Tile::Indefinite(states) => states.len(),
+            };
+            *histogram.entry(count).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Returns whether every tile is definite
+    #[inline(always)]
+    pub fn is_collapsed(&self) -> bool {
+        self.indefinite_count == 0
+    }
+
+    /// Returns every tile in the map, left-to-right then top-to-bottom
+    #[inline(always)]
+    pub fn tiles(&self) -> &[Tile<T>] {
+        &self.map
+    }
+
+    /// Returns a copy of the map with every state turned into a [U] by [f], e.g. for rendering
+    ///
+    /// Definite tiles stay definite and indefinite ones stay indefinite, even if [f] maps several
+    /// of their states to the same value
+    pub fn map<U: State, F: Fn(&T) -> U>(&self, f: F) -> Vec<Tile<U>> {
+        self.map.iter()
+            .map(|tile| tile.map(&f))
+            .collect()
+    }
+
+    /// Consumes the WFC and returns its map as it is, e.g. to draw what was generated before a
+    /// run ended in a contradiction
+    pub fn into_partial(self) -> Vec<Tile<T>> {
+        self.map
+    }
+
     /// Returns a new view centered at [x], [y]
-    pub fn view(&self, idx: usize) -> WfcView<T, R> {
+    pub fn view(&self, idx: usize) -> WfcView<'_, T, R> {
         assert!(idx < self.width * self.height, "x & y must be inside wfc map");
         WfcView {
             pos: (idx % self.width, idx / self.width),
@@ -77,13 +465,211 @@ impl<T: State, R: WfcRules<T>> Wfc<T, R> {
         }
     }
 
+    /// Returns a view of the tile at [x], [y]
+    ///
+    /// # Panics
+    /// * If [x] >= self.width() or [y] >= self.height()
+    pub fn view_xy(&self, x: usize, y: usize) -> WfcView<'_, T, R> {
+        assert!(x < self.width && y < self.height, "x & y must be inside wfc map");
+        self.view(self.xy_pair(x, y))
+    }
+
     /// Converts an xy-pair into two (x, y) coordinates
     pub fn xy_pair(&self, x: usize, y: usize) -> usize {
         y * self.width + x
     }
+
+    /// Returns how the tiles of the map are laid out
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    /// Returns whether tiles on opposite edges of the map are neighbors
+    pub fn is_wrapping(&self) -> bool {
+        self.wrapping
+    }
+
+    /// Returns the indices of the in-bounds tiles next to [idx], ordered as in
+    /// [Topology::offsets]
+    ///
+    /// On the default square topology these are the orthogonal neighbors in [Direction::ALL] order
+    pub fn neighbors_of(&self, idx: usize) -> Vec<usize> {
+        let (x, y) = (idx % self.width, idx / self.width);
+        self.topology.offsets(x, y).iter()
+            .filter_map(|offset| self.offset_index(x, y, *offset))
+            .collect()
+    }
+
+    /// Returns the index of the tile [offset] away from [x], [y], wrapping around the edges if
+    /// the map wraps, or None if that is past the edge of the map
+    fn offset_index(&self, x: usize, y: usize, (dx, dy): (isize, isize)) -> Option<usize> {
+        let step = |v: usize, d: isize, len: usize| match self.wrapping {
+            true => Some((v as isize + d).rem_euclid(len as isize) as usize),
+            false => v.checked_add_signed(d).filter(|v| *v < len),
+        };
+        Some(self.xy_pair(step(x, dx, self.width)?, step(y, dy, self.height)?))
+    }
+
+    /// Returns every tile in the map along with its x & y, left-to-right then top-to-bottom
+    pub fn iter(&self) -> impl Iterator<Item=(usize, usize, &Tile<T>)> + '_ {
+        self.map.iter()
+            .enumerate()
+            .map(|(idx, tile)| (idx % self.width, idx / self.width, tile))
+    }
+
+    /// Returns the x, y & state of every definite tile in the map, left-to-right then
+    /// top-to-bottom
+    pub fn iter_definite(&self) -> impl Iterator<Item=(usize, usize, &T)> + '_ {
+        self.iter().filter_map(|(x, y, tile)| match tile {
+            Tile::Definite(state) => Some((x, y, state)),
+            Tile::Indefinite(_) => None,
+        })
+    }
+
+    /// Returns the indices of every indefinite tile that may still be [state], and of the definite
+    /// tiles that are [state] too if [include_definite] is set, in ascending order
+    pub fn cells_with_candidate(&self, state: &T, include_definite: bool) -> Vec<usize> {
+        self.map.iter()
+            .enumerate()
+            .filter(|(_, tile)| match tile {
+                Tile::Definite(definite) => include_definite && definite == state,
+                Tile::Indefinite(states) => states.contains(state),
+            })
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Returns the indices of every indefinite tile in the map
+    pub fn indefinite_indices(&self) -> impl Iterator<Item=usize> + '_ {
+        self.map.iter()
+            .enumerate()
+            .filter(|(_, tile)| matches!(tile, Tile::Indefinite(_)))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Returns the indices of every definite tile in the map
+    pub fn definite_indices(&self) -> impl Iterator<Item=usize> + '_ {
+        self.map.iter()
+            .enumerate()
+            .filter(|(_, tile)| matches!(tile, Tile::Definite(_)))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Returns the indices of every indefinite tile with no states left
+    pub fn contradictions(&self) -> Vec<usize> {
+        self.map.iter()
+            .enumerate()
+            .filter(|(_, tile)| matches!(tile, Tile::Indefinite(states) if states.is_empty()))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Draws the map as one line per row, turning each tile into a char with [f]
+    pub fn render<F: Fn(&Tile<T>) -> char>(&self, f: F) -> String {
+        self.map.chunks(self.width)
+            .map(|row| row.iter().map(&f).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns the map as an 8-bit RGB image buffer, one pixel per tile, coloring each tile with
+    /// [f]
+    ///
+    /// Pixels are row-major with three bytes each, so the buffer can be turned into an image with
+    /// `image::RgbImage::from_raw(width, height, pixels)`. Returns [WfcError::NotCollapsed] if
+    /// any tile is still indefinite
+    pub fn to_rgb<F: Fn(&T) -> [u8; 3]>(&self, f: F) -> Result<Vec<u8>, WfcError> {
+        let mut pixels = Vec::with_capacity(self.map.len() * 3);
+        for (idx, tile) in self.map.iter().enumerate() {
+            match tile {
+                Tile::Definite(state) => pixels.extend(f(state)),
+                Tile::Indefinite(_) => return Err(WfcError::NotCollapsed(idx)),
+            }
+        }
+        Ok(pixels)
+    }
+
+    /// Returns a span of the elements in the row at [row]
+    ///
+    /// # Panics
+    /// * If row >= self.height()
+    pub fn row(&self, row: usize) -> Span<'_, T> {
+        assert!(row < self.height, "row must be inside of the map's height");
+        let idx = row * self.width;
+        Span::from_slices(vec![&self.map[idx..idx + self.width]])
+    }
+
+    /// Returns a span of the the elements in the column at [col]
+    ///
+    /// # Panics
+    /// * If col >= self.width()
+    pub fn col(&self, col: usize) -> Span<'_, T> {
+        assert!(col < self.width, "column must be inside of the map's width");
+        Span::from_slices(self.map.as_slice()
+            .chunks(self.width)
+            .map(|chunk| &chunk[col..col + 1])
+            .collect())
+    }
+
+    /// Returns a span of the elements in the rectangle formed by the area of [x] and [y]
+    ///
+    /// # Panics
+    /// * If [x].len() == 0
+    /// * If [y].len() == 0
+    /// * If [x].end > self.width()
+    /// * If [y].end > self.height()
+    pub fn span(&self, x: Range<usize>, y: Range<usize>) -> Span<'_, T> {
+        assert_ne!(x.len(), 0, "x-range cannot be zero-width");
+        assert_ne!(y.len(), 0, "y-range cannot be zero-height");
+        assert!(x.end <= self.width, "x-range must be inside of the map's width");
+        assert!(y.end <= self.height, "y-range must be inside of the map's height");
+
+        Span::from_slices(self.map.as_slice()
+            .chunks(self.width)
+            .take(y.end)
+            .skip(y.start)
+            .map(move |chunk| &chunk[x.clone()])
+            .collect())
+    }
+
+    /// Returns a copy of the map which can later be handed to [restore]
+    pub fn snapshot(&self) -> WfcSnapshot<T> {
+        WfcSnapshot(self.map.clone())
+    }
+
+    /// Puts the map back to how it was when [snap] was taken
+    ///
+    /// # Panics
+    /// * If [snap] was taken from a map of a different size
+    pub fn restore(&mut self, snap: WfcSnapshot<T>) {
+        assert_eq!(snap.0.len(), self.map.len(), "snapshot must be from a map of the same size");
+        self.map = snap.0;
+        self.recount();
+    }
+}
+
+impl<T: State, R: WfcRules<T>> Index<usize> for Wfc<T, R> {
+    type Output = Tile<T>;
+
+    /// Returns the tile at [idx]
+    ///
+    /// # Panics
+    /// * If [idx] >= self.len()
+    fn index(&self, idx: usize) -> &Tile<T> {
+        &self.map[idx]
+    }
 }
 
+/// A saved copy of a WFC map, see [Wfc::snapshot]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WfcSnapshot<T: State>(Vec<Tile<T>>);
+
 impl<T: State> Tile<T> {
+    /// Returns whether this tile has been collapsed to a single state
+    pub fn is_definite(&self) -> bool {
+        matches!(self, Tile::Definite(_))
+    }
+
     pub fn as_definite(&self) -> &T {
         match self {
             Tile::Definite(s) => s,
@@ -111,97 +697,2003 @@ impl<T: State> Tile<T> {
             _ => panic!("as_indefinite called on variant that was not Tile::Definite"),
         }
     }
+
+    /// Turns an indefinite tile into a definite one with a uniformly random choice of its states,
+    /// returning the states that were not chosen
+    ///
+    /// Returns None, leaving the tile as it was, if it was definite or had no states
+    pub fn collapse<R: Rng + ?Sized>(&mut self, rng: &mut R) -> Option<BTreeSet<T>> {
+        let states = match self {
+            Tile::Indefinite(states) if !states.is_empty() => states,
+            _ => return None,
+        };
+        let choice = rng.gen_range(0..states.len());
+        let state = states.iter().nth(choice).unwrap().clone();
+
+        let mut states = replace(self, Tile::Definite(state.clone())).into_indefinite();
+        states.remove(&state);
+        Some(states)
+    }
+
+    /// Returns this tile with every state turned into a [U] by [f], keeping it definite or
+    /// indefinite
+    pub fn map<U: State, F: Fn(&T) -> U>(&self, f: F) -> Tile<U> {
+        match self {
+            Tile::Definite(state) => Tile::Definite(f(state)),
+            Tile::Indefinite(states) => Tile::Indefinite(states.iter().map(f).collect()),
+        }
+    }
+
+    /// Returns the state of this tile if it is definite, or [default] if it is not
+    pub fn definite_or<'a>(&'a self, default: &'a T) -> &'a T {
+        match self {
+            Tile::Definite(state) => state,
+            Tile::Indefinite(_) => default,
+        }
+    }
+
+    /// Returns the states this tile may be, which is just its state if it is definite
+    pub fn candidates(&self) -> Cow<'_, BTreeSet<T>> {
+        match self {
+            Tile::Definite(state) => Cow::Owned(BTreeSet::from([state.clone()])),
+            Tile::Indefinite(states) => Cow::Borrowed(states),
+        }
+    }
+
+    /// Replaces this tile with [new] if they differ, returning whether it changed
+    pub fn replace_if_changed(&mut self, new: Tile<T>) -> bool {
+        if *self == new {
+            return false;
+        }
+        *self = new;
+        true
+    }
+
+    /// Like [collapse], but picks each state in proportion to its [weight]
+    ///
+    /// States with a weight that is not positive are never picked unless all of them are, in
+    /// which case the choice is uniform
+    pub fn collapse_weighted<R: Rng + ?Sized>(&mut self, rng: &mut R, weight: impl Fn(&T) -> f64) -> Option<BTreeSet<T>> {
+        let states = match self {
+            Tile::Indefinite(states) if !states.is_empty() => states,
+            _ => return None,
+        };
+        let weights = states.iter()
+            .map(|state| Some(weight(state)).filter(|w| *w > 0.0 && w.is_finite()).unwrap_or(0.0))
+            .collect::<Vec<_>>();
+        let total = weights.iter().sum::<f64>();
+        if total <= 0.0 || !total.is_finite() {
+            return self.collapse(rng);
+        }
+
+        let mut target = rng.gen::<f64>() * total;
+        // Rounding can leave a little of the target over, so fall back to the last weighted state
+        let mut choice = weights.iter().rposition(|w| *w > 0.0).unwrap();
+        for (i, w) in weights.iter().enumerate() {
+            if *w > 0.0 && target < *w {
+                choice = i;
+                break;
+            }
+            target -= w;
+        }
+        let state = states.iter().nth(choice).unwrap().clone();
+
+        let mut states = replace(self, Tile::Definite(state.clone())).into_indefinite();
+        states.remove(&state);
+        Some(states)
+    }
 }
 
 impl<T: State, R: WfcRules<T>> Wfc<T, R> {
+    /// Collapses the lowest entropy tile and propagates the change
+    ///
+    /// Returns None once every tile is definite or on any error, see [step_result] for which
+    #[cfg(feature = "std")]
     pub fn step(&mut self) -> Option<()> {
-        let entropy_map = {
-            let mut map = self.map
-                .iter()
-                .filter(|tile| matches!(tile, Tile::Indefinite(_)))
-                .map(|tile| self.rules.entropy(tile))
-                .enumerate()
-                .collect::<Vec<_>>();
-            map.sort_by(|(_, a), (_, b)|
-                a.partial_cmp(b).expect("Unable to compare tiles!"));
-            map
-        };
+        let outcome = self.step_result();
+        matches!(outcome, Ok(StepOutcome::Collapsed { .. } | StepOutcome::Backtracked)).then_some(())
+    }
+
+    /// Like [try_step], but says what the step did
+    ///
+    /// Contradictions are returned as outcomes, leaving errors for broken rules
+    #[cfg(feature = "std")]
+    pub fn step_result(&mut self) -> Result<StepOutcome<T>, WfcError> {
+        self.step_result_with(&mut thread_rng())
+    }
 
-        if entropy_map.is_empty() {
-            return None; // This means the filter removed everything so every state is definite
+    /// Like [step_result], but makes every random choice with [rng]
+    pub fn step_result_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<StepOutcome<T>, WfcError> {
+        match self.step_diff_with(rng) {
+            Ok(None) => Ok(StepOutcome::Done),
+            Ok(Some(diff)) => Ok(match &self.map[diff.collapsed] {
+                Tile::Definite(value) => StepOutcome::Collapsed { idx: diff.collapsed, value: value.clone() },
+                Tile::Indefinite(_) => StepOutcome::Backtracked,
+            }),
+            Err(WfcError::Contradiction { idx, .. }) => Ok(StepOutcome::Contradiction { idx }),
+            Err(err) => Err(err),
         }
+    }
 
-        let next_highest = {
-            let mut iter = entropy_map.iter();
-            let (_, highest_entropy) = iter.next().unwrap();
-            iter.position(|(_, e)| e.ne(highest_entropy))
-        };
-        let selected = match next_highest {
-            Some(next_highest) => {
-                // collapse random tile in 0..next_highest
-                let mut rng = thread_rng();
-                let tiles = &entropy_map[0..next_highest];
-                tiles.choose(&mut rng)
-            }
-            None => {
-                // collapse random tile
-                let mut rng = thread_rng();
-                entropy_map.choose(&mut rng)
-            }
-        }.expect("No states left!")
-            .0;
-
-        let old = {
-            let states = self.map[selected].as_indefinite();
-            let mut rng = thread_rng();
-            let idx = rng.gen_range(0..states.len());
-            let state = states.iter().nth(idx).unwrap().clone();
-
-            let mut states = replace(&mut self.map[selected], Tile::Definite(state))
-                .into_indefinite();
-            states.remove(&state);
-            states
-        };
+    /// Collapses the lowest entropy tile and propagates the change
+    ///
+    /// Returns Ok(false) once every tile is definite
+    #[cfg(feature = "std")]
+    pub fn try_step(&mut self) -> Result<bool, WfcError> {
+        self.try_step_with(&mut thread_rng())
+    }
+
+    /// Like [try_step], but makes every random choice with [rng]
+    pub fn try_step_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<bool, WfcError> {
+        self.step_diff_with(rng).map(|diff| diff.is_some())
+    }
+
+    /// Steps until every tile is definite
+    #[cfg(feature = "std")]
+    pub fn run(&mut self) -> Result<(), WfcError> {
+        self.validate()?;
+        while self.try_step()? {}
+        Ok(())
+    }
+
+    /// Steps until every tile is definite, giving up with [WfcError::StepLimit] after
+    /// [max_steps] steps
+    #[cfg(feature = "std")]
+    pub fn run_bounded(&mut self, max_steps: usize) -> Result<(), WfcError> {
+        self.validate()?;
+        for _ in 0..max_steps {
+            if !self.try_step()? {
+                return Ok(());
+            }
+        }
+        match self.indefinite_indices().next() {
+            Some(_) => Err(WfcError::StepLimit),
+            None => Ok(()),
+        }
+    }
+
+    /// Steps until every tile is definite, undoing guesses that lead to a contradiction and
+    /// trying their other states instead
+    ///
+    /// Returns [WfcError::Contradiction] only if no combination of guesses works.
+    /// How the solution was reached is recorded in [stats]
+    ///
+    /// Each state of a guess is tried at most once and guesses are undone newest first, so no
+    /// arrangement of the map is visited twice and this always finishes
+    #[cfg(feature = "std")]
+    pub fn run_with_backtracking(&mut self) -> Result<(), WfcError> {
+        self.run_with_backtracking_with(&mut thread_rng())
+    }
+
+    /// Like [run_with_backtracking], but makes every random choice with [rng]
+    pub fn run_with_backtracking_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<(), WfcError> {
+        self.validate()?;
+        self.stats = SolveStats::default();
+        let forced = self.propagate_all().map_err(|idx| self.contradiction(idx))?;
+        self.count_forced(&forced);
+
+        // Every change since the first guess, so a guess is undone by replaying it in reverse
+        let mut journal = Vec::new();
+        let mut guesses = Vec::new();
+        loop {
+            let selected = match self.select_next(rng)? {
+                Some(selected) => selected,
+                None => return Ok(()),
+            };
+            let valid = self.allowed_states(selected);
+            let untried = self.map[selected].as_indefinite()
+                .intersection(&valid)
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            guesses.push(Guess { journal: journal.len(), record: self.record.len(), idx: selected, untried });
+
+            // Try the newest guess's other states, falling back to older guesses as they run out
+            loop {
+                let guess = match guesses.last_mut() {
+                    Some(guess) => guess,
+                    None => {
+                        self.undo_journal(&mut journal, 0);
+                        return Err(self.contradiction(selected));
+                    }
+                };
+                let state = match guess.untried.iter().choose(rng) {
+                    Some(state) => state.clone(),
+                    None => {
+                        guesses.pop();
+                        continue;
+                    }
+                };
+                guess.untried.remove(&state);
+                let idx = guess.idx;
+                self.undo_journal(&mut journal, guess.journal);
+                self.record.truncate(guess.record);
+
+                let old = self.set(idx, Tile::Definite(state.clone()));
+                journal.push((idx, old));
+                self.stats.guesses += 1;
+                let start = journal.len();
+                let seeds = self.rules.dependents(self.view(idx));
+                match self.propagate_journaled(seeds, &mut journal) {
+                    Ok(()) => {
+                        self.record.push((idx, state));
+                        self.count_forced(&journaled_indices(&journal[start..]));
+                        break;
+                    }
+                    // The changes stay in the journal and are undone before the next attempt
+                    Err(_) => self.stats.backtracks += 1,
+                }
+            }
+        }
+    }
 
-        if entropy_map.is_empty() {
-            return None;
+    /// Generates the whole map from scratch, starting again with the next seed whenever it runs
+    /// into a contradiction
+    ///
+    /// Each attempt resets every tile to [all_states] and steps with a [StdRng] seeded with
+    /// [base_seed] plus the attempt number, so the same seed always gives the same map. Returns
+    /// the last contradiction once [max_attempts] attempts have failed, or [WfcError::StepLimit]
+    /// if [max_attempts] is zero
+    #[cfg(feature = "std")]
+    pub fn generate(&mut self, all_states: BTreeSet<T>, max_attempts: usize, base_seed: u64) -> Result<(), WfcError> {
+        let mut result = Err(WfcError::StepLimit);
+        for attempt in 0..max_attempts {
+            self.reset_uniform(all_states.clone());
+            if attempt == 0 {
+                self.validate()?;
+            }
+            let mut rng = StdRng::seed_from_u64(base_seed.wrapping_add(attempt as u64));
+            result = self.run_with_rng(&mut rng);
+            match result {
+                Err(WfcError::Contradiction { .. }) => continue,
+                _ => return result,
+            }
         }
+        result
+    }
+
+    /// Steps with [rng] until every tile is definite
+    fn run_with_rng<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<(), WfcError> {
+        while self.try_step_with(rng)? {}
+        Ok(())
+    }
 
-        let mut valid = true;
-        let mut states = Vec::with_capacity(entropy_map.len() - 1);
-        for (idx, _) in entropy_map {
-            if idx == selected {
-                continue; // This is the collapsed state;
+    /// Resets every tile that is not frozen to [Tile::Indefinite] with [all_states] and clears
+    /// the choice log
+    pub fn reset_uniform(&mut self, all_states: BTreeSet<T>) {
+        for (tile, frozen) in self.map.iter_mut().zip(&self.frozen) {
+            if !frozen {
+                *tile = Tile::Indefinite(all_states.clone());
             }
-            let view = self.view(idx);
-            let collapsed = self.rules.get_states(view);
-            match collapsed.len() {
-                0 => {
-                    valid = false;
-                    break;
+        }
+        self.recount();
+        self.record.clear();
+    }
+
+    /// Freezes the definite tile at [idx], so [reset_uniform] keeps it and it can no longer be
+    /// collapsed or banned, e.g. for the givens of a puzzle
+    ///
+    /// Returns [WfcError::NotCollapsed] if the tile is indefinite
+    pub fn freeze(&mut self, idx: usize) -> Result<(), WfcError> {
+        if !self.map[idx].is_definite() {
+            return Err(WfcError::NotCollapsed(idx));
+        }
+        self.frozen[idx] = true;
+        Ok(())
+    }
+
+    /// Returns whether the tile at [idx] was frozen with [freeze]
+    pub fn is_frozen(&self, idx: usize) -> bool {
+        self.frozen[idx]
+    }
+
+    /// Returns how the last call to [run_with_backtracking] reached its solution
+    pub fn stats(&self) -> SolveStats {
+        self.stats
+    }
+
+    /// Adds the tiles in [updated] that propagation made definite to the forced moves
+    fn count_forced(&mut self, updated: &[usize]) {
+        self.stats.forced_moves += updated.iter()
+            .filter(|idx| self.map[**idx].is_definite())
+            .count();
+    }
+
+    /// Repeatedly collapses the tiles that the rules only allow one state for, never guessing
+    ///
+    /// Returns Ok(true) if that made every tile definite, or Ok(false) if it stalled
+    pub fn solve_logical(&mut self) -> Result<bool, WfcError> {
+        loop {
+            let mut changed = false;
+            for idx in 0..self.map.len() {
+                if self.map[idx].is_definite() {
+                    continue;
                 }
-                _ => states.push((idx, collapsed)),
-            };
+                let valid = self.allowed_states(idx);
+                let states = self.map[idx].as_indefinite();
+                let mut states = states.intersection(&valid);
+                match (states.next(), states.next()) {
+                    (None, _) => return Err(self.contradiction(idx)),
+                    (Some(state), None) => {
+                        let state = state.clone();
+                        self.set(idx, Tile::Definite(state));
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !changed {
+                return Ok(self.is_collapsed());
+            }
         }
+    }
+
+    /// Propagates until no tile's states can be narrowed any further, collapsing every tile that
+    /// is left with a single state but never guessing
+    ///
+    /// Returns how many tiles became definite. On a contradiction the map is unchanged
+    pub fn collapse_all_forced(&mut self) -> Result<usize, WfcError> {
+        let before = self.definite_indices().count();
+        self.propagate_all().map_err(|idx| self.contradiction(idx))?;
+        Ok(self.definite_indices().count() - before)
+    }
 
-        if !valid {
-            if old.is_empty() {
-                return None; // No alternatives for the selected tile; Todo: work on history
+    /// Returns a [WfcError::Contradiction] at the tile at [idx]
+    fn contradiction(&self, idx: usize) -> WfcError {
+        WfcError::Contradiction { idx, x: idx % self.width, y: idx / self.width }
+    }
+
+    /// Runs [WfcRules::validate] against every state present in the map
+    fn validate(&self) -> Result<(), WfcError> {
+        self.rules.validate(&self.all_states()).map_err(WfcError::InvalidRules)
+    }
+
+    /// Returns every state present in the map, definite or not
+    fn all_states(&self) -> BTreeSet<T> {
+        let mut all_states = BTreeSet::new();
+        for tile in &self.map {
+            match tile {
+                Tile::Definite(state) => {
+                    all_states.insert(state.clone());
+                }
+                Tile::Indefinite(states) => all_states.extend(states.iter().cloned()),
             }
-            // Since we removed the randomly chosen state from the old vec,
-            // The next iteration will not make the same mistake
-            self.map[selected] = Tile::Indefinite(old);
         }
+        all_states
+    }
 
-        for (idx, states) in states {
-            let tile = match states.len() {
-                0 => unreachable!(),
-                1 => Tile::Definite(states.into_iter().next().unwrap()),
-                _ => Tile::Indefinite(states),
-            };
-            self.map[idx] = tile;
+    /// Like [try_step], but returns which tiles changed so they can be redrawn
+    ///
+    /// Returns Ok(None) once every tile is definite
+    #[cfg(feature = "std")]
+    pub fn step_diff(&mut self) -> Result<Option<StepDiff>, WfcError> {
+        self.step_diff_with(&mut thread_rng())
+    }
+
+    /// Like [step_diff], but makes every random choice with [rng]
+    pub fn step_diff_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<Option<StepDiff>, WfcError> {
+        if self.is_collapsed() {
+            return Ok(None); // Nothing to select, so skip ranking the tiles by entropy
+        }
+        let selected = match self.select_next(rng)? {
+            Some(selected) => selected,
+            None => return Ok(None), // Every state is definite
+        };
+
+        let old = self.collapse(selected, rng)?;
+        let updated = match self.propagate(selected) {
+            Ok(updated) => {
+                self.record.push((selected, self.map[selected].as_definite().clone()));
+                updated
+            }
+            Err(contradiction) => {
+                if old.is_empty() {
+                    // No alternatives for the selected tile; Todo: work on history
+                    return Err(self.contradiction(contradiction));
+                }
+                // Since we removed the randomly chosen state from the old vec,
+                // The next iteration will not make the same mistake
+                self.set(selected, Tile::Indefinite(old));
+                vec![]
+            }
+        };
+
+        Ok(Some(StepDiff { collapsed: selected, updated }))
+    }
+
+    /// Picks the next tile to collapse with the selection strategy, or by lowest entropy if there
+    /// is none. Returns None if every tile is definite
+    fn select_next<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<Option<usize>, WfcError> {
+        match self.selection.take() {
+            Some(mut selection) => {
+                let selected = selection.select(self);
+                self.selection = Some(selection);
+                Ok(selected)
+            }
+            None => self.select_min_entropy(rng),
+        }
+    }
+
+    /// Returns the entropy the tile at [idx] is ranked by, including its noise
+    fn entropy_of(&self, idx: usize) -> f64 {
+        let noise = self.noise.get(idx).copied().unwrap_or(0.0);
+        self.rules.entropy_at(self.view(idx)) + noise
+    }
+
+    /// Returns every indefinite tile with its entropy, lowest entropy first
+    ///
+    /// Includes the noise from [with_noise_seed], and tiles of equal entropy stay in index order.
+    /// [step] picks among the lowest of these, after adding any jitter
+    pub fn entropy_ranking(&self) -> Vec<(usize, f64)> {
+        let mut ranking = self.indefinite_indices()
+            .map(|idx| (idx, self.entropy_of(idx)))
+            .collect::<Vec<_>>();
+        ranking.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        ranking
+    }
+
+    /// Returns a random tile among those with the lowest entropy, or None if all are definite
+    fn select_min_entropy<G: Rng + ?Sized>(&self, rng: &mut G) -> Result<Option<usize>, WfcError> {
+        let tied = self.lowest_entropy_tiles(rng)?;
+        Ok(self.break_tie(&tied, rng))
+    }
+
+    /// Returns every indefinite tile tied for the lowest entropy once any jitter is added, in
+    /// ascending order
+    ///
+    /// Finds the same lowest tiles as [entropy_ranking], but in a single pass without sorting so
+    /// it stays cheap to call every frame. This is the entropy phase of [step]
+    pub(crate) fn lowest_entropy_tiles<G: Rng + ?Sized>(&self, rng: &mut G) -> Result<Vec<usize>, WfcError> {
+        let mut lowest = f64::INFINITY;
+        let mut tied = Vec::new();
+        for idx in self.indefinite_indices() {
+            let mut entropy = self.entropy_of(idx);
+            if entropy.is_nan() {
+                return Err(WfcError::InvalidEntropy(idx));
+            }
+            if self.jitter != 0.0 {
+                entropy += self.jitter * rng.gen::<f64>();
+            }
+
+            // Only the tiles at the lowest entropy are eligible, wherever the ties fall
+            if entropy < lowest {
+                lowest = entropy;
+                tied.clear();
+            }
+            if entropy == lowest {
+                tied.push(idx);
+            }
+        }
+        Ok(tied)
+    }
+
+    /// Picks one of the [tied] tiles by the [TieBreak], or None if there are none
+    ///
+    /// This is the selection phase of [step]
+    pub(crate) fn break_tie<G: Rng + ?Sized>(&self, tied: &[usize], rng: &mut G) -> Option<usize> {
+        match self.tie_break {
+            TieBreak::Random => tied.choose(rng).copied(),
+            TieBreak::FirstIndex => tied.first().copied(),
+            TieBreak::LastIndex => tied.last().copied(),
         }
+    }
+
+    /// Returns how many more steps generation should take, which is how many tiles are still
+    /// indefinite since each step makes at least one definite
+    ///
+    /// Propagation usually collapses tiles too, so this is an upper bound on the steps of a run
+    /// without contradictions
+    pub fn remaining_steps_estimate(&self) -> usize {
+        self.indefinite_count
+    }
 
-        Some(())
+    /// Collapses a random tile of the lowest entropy without propagating, returning its index and
+    /// the state it became, so the steps of the algorithm can be shown one at a time
+    ///
+    /// Call [propagate_at] afterwards to update the other tiles. Returns None if every
+    /// tile is definite, the rules allow none of the chosen tile's states or give a NaN entropy
+    #[cfg(feature = "std")]
+    pub fn collapse_lowest(&mut self) -> Option<(usize, T)> {
+        self.collapse_lowest_with(&mut thread_rng())
     }
-}
\ No newline at end of file
+
+    /// Like [collapse_lowest], but makes every random choice with [rng]
+    pub fn collapse_lowest_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Option<(usize, T)> {
+        let selected = self.select_min_entropy(rng).ok()??;
+        self.collapse(selected, rng).ok()?;
+        let state = self.map[selected].as_definite().clone();
+        self.record.push((selected, state.clone()));
+        Some((selected, state))
+    }
+
+    /// Re-derives the tiles depending on the tile at [idx] until nothing changes, returning the
+    /// indices of those that changed in ascending order
+    ///
+    /// Nothing is changed if that would leave a tile without states. This is the propagation phase
+    /// of [step]
+    pub fn propagate_at(&mut self, idx: usize) -> Result<Vec<usize>, WfcError> {
+        self.propagate(idx).map_err(|idx| self.contradiction(idx))
+    }
+
+    /// Collapses the tile at [idx] to a random one of its states and propagates the change
+    ///
+    /// On a contradiction the tile is returned to [Tile::Indefinite] without the chosen state
+    #[cfg(feature = "std")]
+    pub fn step_at(&mut self, idx: usize) -> Result<(), WfcError> {
+        self.step_at_with(idx, &mut thread_rng())
+    }
+
+    /// Like [step_at], but makes the random choice with [rng]
+    pub fn step_at_with<G: Rng + ?Sized>(&mut self, idx: usize, rng: &mut G) -> Result<(), WfcError> {
+        match &self.map[idx] {
+            _ if self.frozen[idx] => return Err(WfcError::Frozen(idx)),
+            Tile::Definite(_) => return Err(WfcError::AlreadyDefinite(idx)),
+            Tile::Indefinite(states) if states.is_empty() => return Err(self.contradiction(idx)),
+            Tile::Indefinite(_) => {}
+        }
+
+        let old = self.collapse(idx, rng)?;
+        if let Err(contradiction) = self.propagate(idx) {
+            self.set(idx, Tile::Indefinite(old));
+            return Err(self.contradiction(contradiction));
+        }
+        self.record.push((idx, self.map[idx].as_definite().clone()));
+        Ok(())
+    }
+
+    /// Collapses the indefinite tile at [idx] to [state] and propagates the change
+    ///
+    /// On an error the map is unchanged
+    pub fn collapse_at(&mut self, idx: usize, state: T) -> Result<(), WfcError> {
+        match &self.map[idx] {
+            _ if self.frozen[idx] => return Err(WfcError::Frozen(idx)),
+            Tile::Definite(_) => return Err(WfcError::AlreadyDefinite(idx)),
+            Tile::Indefinite(states) if !states.contains(&state) => return Err(self.contradiction(idx)),
+            Tile::Indefinite(_) => {}
+        }
+
+        let old = self.set(idx, Tile::Definite(state.clone()));
+        if let Err(contradiction) = self.propagate(idx) {
+            self.set(idx, old);
+            return Err(self.contradiction(contradiction));
+        }
+        self.record.push((idx, state));
+        Ok(())
+    }
+
+    /// Returns every (index, state) collapse made so far, in order
+    pub fn choice_log(&self) -> &[(usize, T)] {
+        &self.record
+    }
+
+    /// Re-applies the collapses in [log], as returned by [choice_log], with propagation
+    pub fn replay(&mut self, log: &[(usize, T)]) -> Result<(), WfcError> {
+        for (idx, state) in log {
+            self.collapse_at(*idx, state.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Removes [state] from the indefinite tile at [idx] and propagates the change
+    ///
+    /// The tile becomes definite if only one state remains. On an error the map is unchanged
+    pub fn ban(&mut self, idx: usize, state: &T) -> Result<(), WfcError> {
+        let mut states = match &self.map[idx] {
+            _ if self.frozen[idx] => return Err(WfcError::Frozen(idx)),
+            Tile::Definite(_) => return Err(WfcError::AlreadyDefinite(idx)),
+            Tile::Indefinite(states) => states.clone(),
+        };
+        states.remove(state);
+        let tile = match states.len() {
+            0 => return Err(self.contradiction(idx)),
+            1 => Tile::Definite(states.into_iter().next().unwrap()),
+            _ => Tile::Indefinite(states),
+        };
+
+        let old = self.set(idx, tile);
+        if let Err(contradiction) = self.propagate(idx) {
+            self.set(idx, old);
+            return Err(self.contradiction(contradiction));
+        }
+        Ok(())
+    }
+
+    /// Removes every state from the indefinite tiles that [keep] returns false for, given the
+    /// tile's index and the state, then propagates the change
+    ///
+    /// Tiles left with one state become definite, and definite tiles are left alone. On an error
+    /// the map is unchanged
+    pub fn constrain<F: Fn(usize, &T) -> bool>(&mut self, keep: F) -> Result<(), WfcError> {
+        let mut changed = Vec::new();
+        for (idx, tile) in self.map.iter().enumerate() {
+            let Tile::Indefinite(states) = tile else { continue };
+            let kept = states.iter()
+                .filter(|state| keep(idx, state))
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            if kept.len() == states.len() {
+                continue;
+            }
+            changed.push((idx, match kept.len() {
+                0 => return Err(self.contradiction(idx)),
+                1 => Tile::Definite(kept.into_iter().next().unwrap()),
+                _ => Tile::Indefinite(kept),
+            }));
+        }
+
+        let mut old = Vec::with_capacity(changed.len());
+        let mut seeds = Vec::new();
+        for (idx, tile) in changed {
+            old.push((idx, self.set(idx, tile)));
+            seeds.extend(self.rules.dependents(self.view(idx)));
+        }
+        if let Err(contradiction) = self.propagate_from(seeds) {
+            for (idx, tile) in old.into_iter().rev() {
+                self.set(idx, tile);
+            }
+            return Err(self.contradiction(contradiction));
+        }
+        Ok(())
+    }
+
+    /// Regenerates the [x]-[y] rectangle of the map, keeping the tiles around it fixed so they
+    /// act as its boundary
+    ///
+    /// Every tile in the rectangle is reset to any of the states present in the map, then they
+    /// are collapsed in lowest entropy order. Frozen tiles are kept as they are and act as part
+    /// of the boundary. On an error the map is unchanged
+    ///
+    /// # Panics
+    /// * If [x].end > self.width()
+    /// * If [y].end > self.height()
+    #[cfg(feature = "std")]
+    pub fn collapse_region(&mut self, x: Range<usize>, y: Range<usize>) -> Result<(), WfcError> {
+        self.collapse_region_with(x, y, &mut thread_rng())
+    }
+
+    /// Like [collapse_region], but makes every random choice with [rng]
+    pub fn collapse_region_with<G: Rng + ?Sized>(
+        &mut self,
+        x: Range<usize>,
+        y: Range<usize>,
+        rng: &mut G,
+    ) -> Result<(), WfcError> {
+        assert!(x.end <= self.width, "x-range must be inside of the map's width");
+        assert!(y.end <= self.height, "y-range must be inside of the map's height");
+
+        let map = self.map.clone();
+        let record = self.record.len();
+        let result = self.regenerate(x, y, rng);
+        if result.is_err() {
+            self.map = map;
+            self.recount();
+            self.record.truncate(record);
+        }
+        result
+    }
+
+    /// Does the work of [collapse_region_with], leaving the map half-generated on an error
+    fn regenerate<G: Rng + ?Sized>(&mut self, x: Range<usize>, y: Range<usize>, rng: &mut G) -> Result<(), WfcError> {
+        let (width, frozen) = (self.width, &self.frozen);
+        let region = y
+            .flat_map(|y| x.clone().map(move |x| y * width + x))
+            .filter(|idx| !frozen[*idx])
+            .collect::<Vec<_>>();
+        let all_states = self.all_states();
+        for idx in &region {
+            self.set(*idx, Tile::Indefinite(all_states.clone()));
+        }
+        self.propagate_from(region.clone()).map_err(|idx| self.contradiction(idx))?;
+
+        // The region is in ascending order, since it was built row by row
+        let in_region = |idx: &usize| region.binary_search(idx).is_ok();
+        loop {
+            let selected = match self.entropy_ranking().into_iter().find(|(idx, _)| in_region(idx)) {
+                Some((selected, _)) => selected,
+                None => return Ok(()),
+            };
+            match self.step_at_with(selected, rng) {
+                // The failed state was removed, so try the tile again unless none are left
+                Err(WfcError::Contradiction { .. }) if !self.map[selected].as_indefinite().is_empty() => {}
+                result => result?,
+            }
+        }
+    }
+
+    /// Returns the states the rules allow at [idx], using and updating the map's cache
+    fn allowed_states(&mut self, idx: usize) -> BTreeSet<T> {
+        let mut cache = take(&mut self.cache);
+        let states = self.rules.get_states_cached(self.view(idx), &mut cache);
+        self.cache = cache;
+        states
+    }
+
+    /// Collapses the indefinite tile at [idx], returning the states that were not chosen
+    ///
+    /// Returns [WfcError::AlreadyDefinite] if the tile is definite, or a contradiction if the
+    /// rules allow none of its states, leaving the map unchanged either way. This is the collapse
+    /// phase of [step]
+    pub(crate) fn collapse<G: Rng + ?Sized>(&mut self, idx: usize, rng: &mut G) -> Result<BTreeSet<T>, WfcError> {
+        if self.map[idx].is_definite() {
+            return Err(WfcError::AlreadyDefinite(idx));
+        }
+        // The stored states may be stale, so only pick from those the rules still allow
+        let valid = self.allowed_states(idx);
+        let mut tile = Tile::Indefinite(self.map[idx].as_indefinite()
+            .intersection(&valid)
+            .cloned()
+            .collect());
+        let rules = &self.rules;
+        let old = tile.collapse_weighted(rng, |state| rules.state_weight(state))
+            .ok_or_else(|| self.contradiction(idx))?;
+        self.set(idx, tile);
+        Ok(old)
+    }
+
+    /// Replaces the tile at [idx] with [tile], keeping the indefinite count up to date, and
+    /// returns the old tile
+    fn set(&mut self, idx: usize, tile: Tile<T>) -> Tile<T> {
+        let old = replace(&mut self.map[idx], tile);
+        self.track(&old, idx);
+        old
+    }
+
+    /// Updates the indefinite count for the tile at [idx] having changed from [old]
+    fn track(&mut self, old: &Tile<T>, idx: usize) {
+        match (old.is_definite(), self.map[idx].is_definite()) {
+            (true, false) => self.indefinite_count += 1,
+            (false, true) => self.indefinite_count -= 1,
+            _ => {}
+        }
+    }
+
+    /// Recounts the indefinite tiles after the whole map was replaced
+    fn recount(&mut self) {
+        self.indefinite_count = self.indefinite_indices().count();
+    }
+
+    /// Re-derives the dependents of [changed], and the dependents of any of those that change,
+    /// until nothing changes. Returns the indices of every tile that changed in ascending order
+    ///
+    /// Nothing is updated if any tile would be left without states, whose index is returned
+    fn propagate(&mut self, changed: usize) -> Result<Vec<usize>, usize> {
+        let seeds = self.rules.dependents(self.view(changed));
+        self.propagate_from(seeds)
+    }
+
+    /// Like [propagate], but re-derives every indefinite tile first
+    fn propagate_all(&mut self) -> Result<Vec<usize>, usize> {
+        let seeds = self.indefinite_indices().collect();
+        self.propagate_from(seeds)
+    }
+
+    /// Re-derives [seeds], and the dependents of any that change, until nothing changes
+    fn propagate_from(&mut self, seeds: Vec<usize>) -> Result<Vec<usize>, usize> {
+        let mut journal = Vec::new();
+        if let Err(contradiction) = self.propagate_journaled(seeds, &mut journal) {
+            self.undo_journal(&mut journal, 0);
+            return Err(contradiction);
+        }
+        Ok(journaled_indices(&journal))
+    }
+
+    /// Restores the old tiles in [journal], newest first, until only the first [len] changes
+    /// are left
+    fn undo_journal(&mut self, journal: &mut Vec<(usize, Tile<T>)>, len: usize) {
+        while journal.len() > len {
+            let (idx, tile) = journal.pop().unwrap();
+            self.set(idx, tile);
+        }
+    }
+
+    /// Does the work of [propagate_from], pushing each tile's old value onto [journal] as it
+    /// changes so the caller can undo them
+    ///
+    /// Each batch of tiles is applied in ascending index order, whatever order the rules listed
+    /// them in, so when two updates conflict the same one always wins
+    fn propagate_journaled(&mut self, seeds: Vec<usize>, journal: &mut Vec<(usize, Tile<T>)>) -> Result<(), usize> {
+        let mut queued = take(&mut self.queued);
+        let result = self.propagate_queued(seeds, journal, &mut queued);
+        queued.fill(false);
+        self.queued = queued;
+        result
+    }
+
+    /// Does the work of [propagate_journaled], marking the tiles in the queue in [queued]
+    fn propagate_queued(
+        &mut self,
+        seeds: Vec<usize>,
+        journal: &mut Vec<(usize, Tile<T>)>,
+        queued: &mut [bool],
+    ) -> Result<(), usize> {
+        let mut queue = VecDeque::new();
+        self.enqueue(seeds, &mut queue, queued);
+
+        let mut depth = 0;
+        while !queue.is_empty() {
+            depth += 1;
+            if self.propagation_radius.is_some_and(|radius| depth > radius) {
+                break;
+            }
+            // Tiles are derived a batch at a time so the map is only read while deriving
+            let mut batch = queue.drain(..).collect::<Vec<_>>();
+            for idx in &batch {
+                queued[*idx] = false;
+            }
+            batch.retain(|idx| matches!(self.map[*idx], Tile::Indefinite(_)));
+            batch.sort_unstable();
+
+            let states = self.derive_states(&batch)?;
+            for (idx, mut states) in batch.into_iter().zip(states) {
+                if states.len() == 1 {
+                    // Tiles in the same batch can settle on conflicting states, so check any that
+                    // become definite against those applied before them
+                    let valid = self.allowed_states(idx);
+                    states.retain(|state| valid.contains(state));
+                }
+                let tile = match states.len() {
+                    0 => return Err(idx),
+                    1 => Tile::Definite(states.into_iter().next().unwrap()),
+                    _ => Tile::Indefinite(states),
+                };
+                let old = self.map[idx].clone();
+                if self.map[idx].replace_if_changed(tile) {
+                    self.track(&old, idx);
+                    journal.push((idx, old));
+                    let dependents = self.rules.dependents(self.view(idx));
+                    self.enqueue(dependents, &mut queue, queued);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues every indefinite tile in [indices] that isn't already queued
+    fn enqueue(&self, indices: Vec<usize>, queue: &mut VecDeque<usize>, queued: &mut [bool]) {
+        for idx in indices {
+            if !queued[idx] && matches!(self.map[idx], Tile::Indefinite(_)) {
+                queued[idx] = true;
+                queue.push_back(idx);
+            }
+        }
+    }
+
+    /// Returns the states of each indefinite tile in [indices] that the rules still allow,
+    /// or the index of the first tile left without any
+    ///
+    /// With the `parallel` feature, batches of at least [with_parallel_min_batch] tiles are split
+    /// between threads instead
+    fn derive_states(&mut self, indices: &[usize]) -> Result<Vec<BTreeSet<T>>, usize> {
+        #[cfg(feature = "parallel")]
+        if indices.len() >= self.parallel_min_batch {
+            return self.derive_states_parallel(indices);
+        }
+        let mut cache = take(&mut self.cache);
+        let states = self.derive_states_sequential(indices, &mut cache);
+        self.cache = cache;
+        states
+    }
+
+    /// Like [derive_states], but splits the tiles between threads since the map is only read
+    ///
+    /// Each thread starts with an empty [WfcRules::Cache] that is dropped afterwards
+    #[cfg(feature = "parallel")]
+    fn derive_states_parallel(&self, indices: &[usize]) -> Result<Vec<BTreeSet<T>>, usize> {
+        let threads = std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1);
+        let chunk = indices.len().div_ceil(threads).max(1);
+        std::thread::scope(|scope| {
+            let handles = indices.chunks(chunk)
+                .map(|indices| scope.spawn(move || {
+                    self.derive_states_sequential(indices, &mut R::Cache::default())
+                }))
+                .collect::<Vec<_>>();
+
+            let mut states = Vec::with_capacity(indices.len());
+            for handle in handles {
+                states.extend(handle.join().expect("propagation thread panicked")?);
+            }
+            Ok(states)
+        })
+    }
+
+    fn derive_states_sequential(&self, indices: &[usize], cache: &mut R::Cache) -> Result<Vec<BTreeSet<T>>, usize> {
+        indices.iter()
+            .map(|idx| {
+                let valid = self.rules.get_states_cached(self.view(*idx), cache);
+                let states = self.map[*idx].as_indefinite()
+                    .intersection(&valid)
+                    .cloned()
+                    .collect::<BTreeSet<_>>();
+                match states.is_empty() {
+                    true => Err(*idx),
+                    false => Ok(states),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Counts of how [Wfc::run_with_backtracking] reached its solution, see [Wfc::stats]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct SolveStats {
+    /// The number of guesses that led to a contradiction and were undone
+    pub backtracks: usize,
+    /// The number of tiles that propagation made definite, including any later undone
+    pub forced_moves: usize,
+    /// The number of states tried for tiles that propagation could not decide
+    pub guesses: usize,
+}
+
+/// A tile collapsed by [Wfc::run_with_backtracking], with what is needed to try it again
+#[derive(Debug)]
+struct Guess<T: State> {
+    /// The length of the journal from before the guess
+    journal: usize,
+    /// The length of the choice log from before the guess
+    record: usize,
+    idx: usize,
+    /// The states that have not been tried yet
+    untried: BTreeSet<T>,
+}
+
+/// Returns the indices of the tiles changed in [journal] in ascending order
+fn journaled_indices<T: State>(journal: &[(usize, Tile<T>)]) -> Vec<usize> {
+    let mut indices = journal.iter()
+        .map(|(idx, _)| *idx)
+        .collect::<Vec<_>>();
+    indices.sort();
+    indices.dedup();
+    indices
+}
+
+/// The tiles changed by a single step, see [Wfc::step_diff]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StepDiff {
+    /// The tile chosen to be collapsed
+    ///
+    /// If the collapse led to a contradiction it is undone, leaving this as the only change
+    pub collapsed: usize,
+    /// Every other tile whose states changed
+    pub updated: Vec<usize>,
+}
+
+/// What a single step did, see [Wfc::step_result]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StepOutcome<T: State> {
+    /// The tile at [idx] was collapsed to [value] and the change propagated
+    Collapsed { idx: usize, value: T },
+    /// Every tile was already definite
+    Done,
+    /// The tile at [idx] was left without states and the chosen tile had nothing else to try
+    Contradiction { idx: usize },
+    /// The chosen state led to a contradiction so it was removed from the tile, which stays
+    /// indefinite
+    Backtracked,
+}
+
+/// The most noise [noisy_shannon_entropy] adds, small enough not to reorder distinct entropies
+pub const ENTROPY_NOISE: f64 = 1e-6;
+
+/// Returns the Shannon entropy of a tile whose states have [weights],
+/// `log(sum w) - (sum w log w) / (sum w)`
+///
+/// Zero weights are ignored, and no weights at all give an entropy of 0
+#[cfg(feature = "std")]
+pub fn shannon_entropy(weights: &[f64]) -> f64 {
+    let sum = weights.iter().sum::<f64>();
+    if sum <= 0.0 {
+        return 0.0;
+    }
+    let sum_log = weights.iter()
+        .filter(|w| **w > 0.0)
+        .map(|w| w * w.ln())
+        .sum::<f64>();
+    sum.ln() - sum_log / sum
+}
+
+/// Returns [shannon_entropy] of [weights] plus up to [ENTROPY_NOISE] of noise from [rng],
+/// so tiles with equal weights are not always picked in the same order
+#[cfg(feature = "std")]
+pub fn noisy_shannon_entropy<G: Rng>(weights: &[f64], rng: &mut G) -> f64 {
+    shannon_entropy(weights) + rng.gen::<f64>() * ENTROPY_NOISE
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rand::rngs::StdRng;
+    use rand::{SeedableRng, thread_rng};
+
+    use crate::wfc::rules::NoAdjacentEqualRules;
+    use crate::wfc::{AdjacencyRules, Direction, ENTROPY_NOISE, noisy_shannon_entropy, ScanlineSelection, shannon_entropy, Span, StepDiff, StepOutcome, Tile, Topology, Wfc, WfcError, WfcRules, WfcView};
+
+    #[derive(Debug)]
+    struct S;
+
+    impl WfcRules<i32> for S {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0])
+        }
+    }
+
+    fn wfc() -> Wfc<i32, S> {
+        Wfc::new(4, 4, (0..16).map(Tile::Definite).collect(), S)
+    }
+
+    fn checkerboard() -> AdjacencyRules<i32> {
+        let mut rules = AdjacencyRules::new();
+        for (a, b) in [(0, 1), (1, 0)] {
+            rules.allow(a, Direction::East, b);
+            rules.allow(a, Direction::South, b);
+        }
+        rules
+    }
+
+    /// Rules allowing anything, with the number of candidates as the entropy
+    #[derive(Debug)]
+    struct Counted;
+
+    impl WfcRules<i32> for Counted {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0, 1, 2])
+        }
+
+        fn entropy(&self, tile: &Tile<i32>) -> f64 {
+            match tile {
+                Tile::Definite(_) => 0.0,
+                Tile::Indefinite(states) => states.len() as f64,
+            }
+        }
+
+        fn dependents(&self, _: WfcView<'_, i32, Self>) -> Vec<usize> {
+            vec![]
+        }
+    }
+
+    #[test]
+    fn step_definite() {
+        let mut wfc = wfc();
+        let before = wfc.map.clone();
+        assert_eq!(wfc.step(), None);
+        assert_eq!(wfc.map, before);
+    }
+
+    #[test]
+    fn try_new_zero_width() {
+        let wfc = Wfc::try_new(0, 4, vec![], S);
+        assert_eq!(wfc.err(), Some(WfcError::InvalidDimensions));
+    }
+
+    #[test]
+    fn try_new_zero_height() {
+        let wfc = Wfc::try_new(4, 0, vec![], S);
+        assert_eq!(wfc.err(), Some(WfcError::InvalidDimensions));
+    }
+
+    #[test]
+    fn try_new_length_mismatch() {
+        let wfc = Wfc::try_new(4, 4, (0..15).map(Tile::Definite).collect(), S);
+        assert_eq!(wfc.err(), Some(WfcError::InvalidDimensions));
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_panics() {
+        Wfc::new(4, 4, (0..15).map(Tile::Definite).collect(), S);
+    }
+
+    #[test]
+    fn snapshot_restore() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16];
+        let mut wfc = Wfc::new(4, 4, tiles, S);
+        let before = wfc.map.clone();
+
+        let snap = wfc.snapshot();
+        assert_eq!(wfc.step(), Some(()));
+        assert_ne!(wfc.map, before);
+
+        wfc.restore(snap);
+        assert_eq!(wfc.map, before);
+    }
+
+    #[test]
+    fn step_at_errors() {
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[1] = Tile::Indefinite(BTreeSet::new());
+        let mut wfc = Wfc::new(4, 4, tiles, S);
+        assert_eq!(wfc.step_at(0), Err(WfcError::AlreadyDefinite(0)));
+        assert_eq!(wfc.step_at(1), Err(WfcError::Contradiction { idx: 1, x: 1, y: 0 }));
+    }
+
+    #[test]
+    fn collapse_phase() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let tiles = vec![Tile::Definite(0), Tile::Indefinite(BTreeSet::from([0, 1]))];
+        let mut wfc = Wfc::new(2, 1, tiles, checkerboard());
+        assert_eq!(wfc.collapse(0, &mut rng), Err(WfcError::AlreadyDefinite(0)));
+        assert_eq!(wfc.collapse(1, &mut rng), Ok(BTreeSet::new()));
+        assert_eq!(wfc.map[1], Tile::Definite(1));
+
+        // The stored state is stale, so the rules allow nothing and the tile is left as it was
+        let tiles = vec![Tile::Definite(0), Tile::Indefinite(BTreeSet::from([0]))];
+        let mut wfc = Wfc::new(2, 1, tiles.clone(), checkerboard());
+        assert_eq!(wfc.collapse(1, &mut rng), Err(WfcError::Contradiction { idx: 1, x: 1, y: 0 }));
+        assert_eq!(wfc.map, tiles);
+        assert_eq!(wfc.remaining_steps_estimate(), 1);
+    }
+
+    #[test]
+    fn definite_partition() {
+        let tiles = (0..16)
+            .map(|i| match i % 2 {
+                0 => Tile::Definite(i),
+                _ => Tile::Indefinite(BTreeSet::from([i])),
+            })
+            .collect();
+        let wfc = Wfc::new(4, 4, tiles, S);
+
+        let definite = wfc.definite_indices().collect::<Vec<_>>();
+        let indefinite = wfc.indefinite_indices().collect::<Vec<_>>();
+        assert_eq!(definite, (0..16).step_by(2).collect::<Vec<_>>());
+        assert_eq!(indefinite, (1..16).step_by(2).collect::<Vec<_>>());
+
+        let mut all = definite;
+        all.extend(indefinite);
+        all.sort();
+        assert_eq!(all, (0..16).collect::<Vec<_>>());
+    }
+
+    #[derive(Debug)]
+    struct NanEntropy;
+
+    impl WfcRules<i32> for NanEntropy {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0])
+        }
+
+        fn entropy(&self, _tile: &Tile<i32>) -> f64 {
+            f64::NAN
+        }
+    }
+
+    #[test]
+    fn nan_entropy() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0])); 4];
+        let mut wfc = Wfc::new(2, 2, tiles, NanEntropy);
+        assert_eq!(wfc.try_step(), Err(WfcError::InvalidEntropy(0)));
+        assert_eq!(wfc.step(), None);
+    }
+
+    #[test]
+    fn error_display() {
+        use std::error::Error;
+
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[6] = Tile::Indefinite(BTreeSet::new());
+        let mut wfc = Wfc::new(4, 4, tiles, S);
+        let contradiction = wfc.step_at(6).unwrap_err().to_string();
+        assert_eq!(contradiction, "tile 6 at (2, 1) was left with no possible states");
+
+        let given = WfcError::InvalidGiven(3, Box::new(WfcError::Frozen(7)));
+        assert_eq!(given.to_string(), "given 3 could not be placed: tile 7 is frozen");
+        assert_eq!(given.source().map(ToString::to_string), Some("tile 7 is frozen".to_string()));
+        assert!(WfcError::StepLimit.source().is_none());
+    }
+
+    #[test]
+    fn jitter() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16];
+        let mut wfc = Wfc::new(4, 4, tiles, S).with_jitter(1e-6);
+        assert_eq!(wfc.try_step(), Ok(true));
+        assert_eq!(wfc.definite_indices().count(), 16);
+        assert_eq!(wfc.try_step(), Ok(false));
+    }
+
+    #[test]
+    fn remaining_steps_estimate() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 25];
+        let mut wfc = Wfc::new(5, 5, tiles, Coloring::default());
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut estimate = wfc.remaining_steps_estimate();
+        assert_eq!(estimate, 25);
+        while let Ok(Some(diff)) = wfc.step_diff_with(&mut rng) {
+            if wfc[diff.collapsed].is_definite() {
+                assert!(wfc.remaining_steps_estimate() < estimate);
+            }
+            estimate = wfc.remaining_steps_estimate();
+        }
+        assert_eq!(wfc.remaining_steps_estimate(), wfc.indefinite_indices().count());
+    }
+
+    #[test]
+    fn from_grid() {
+        let grid = vec![
+            (0..3).map(Tile::Definite).collect(),
+            (3..6).map(Tile::Definite).collect(),
+        ];
+        let wfc = Wfc::from_grid(grid, S).unwrap();
+        assert_eq!(wfc.width(), 3);
+        assert_eq!(wfc.height(), 2);
+        assert_eq!(wfc.map, (0..6).map(Tile::Definite).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn from_grid_ragged() {
+        let grid = vec![
+            (0..3).map(Tile::Definite).collect(),
+            (3..6).map(Tile::Definite).collect(),
+            (6..8).map(Tile::Definite).collect(),
+        ];
+        assert_eq!(Wfc::from_grid(grid, S).err(), Some(WfcError::RaggedRow(2)));
+    }
+
+    #[test]
+    fn step_diff() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 9];
+        let mut wfc = Wfc::new(3, 3, tiles, checkerboard()).with_selection(ScanlineSelection);
+
+        let diff = wfc.step_diff();
+        assert_eq!(diff, Ok(Some(StepDiff { collapsed: 0, updated: (1..9).collect() })));
+    }
+
+    #[test]
+    fn backtracking_finishes() {
+        // A 3x3 torus is made of odd loops, so it can never be two-colored
+        let states = BTreeSet::from([0, 1]);
+        let tiles = vec![Tile::Indefinite(states.clone()); 9];
+        let rules = NoAdjacentEqualRules::new(states);
+        let mut wfc = Wfc::new(3, 3, tiles, rules).with_wrapping(true);
+        let result = wfc.run_with_backtracking_with(&mut StdRng::seed_from_u64(0));
+        assert!(matches!(result, Err(WfcError::Contradiction { .. })));
+        assert_eq!((wfc.stats().guesses, wfc.stats().backtracks), (2, 2));
+        assert_eq!(wfc.tiles(), vec![Tile::Indefinite(BTreeSet::from([0, 1])); 9]);
+    }
+
+    #[test]
+    fn candidate_histogram() {
+        let tiles = vec![
+            Tile::Definite(0), Tile::Indefinite(BTreeSet::from([0])), Tile::Indefinite(BTreeSet::from([0, 1])),
+            Tile::Indefinite(BTreeSet::from([1, 2])), Tile::Indefinite(BTreeSet::from([0, 1, 2])), Tile::Definite(2),
+        ];
+        let wfc = Wfc::new(3, 2, tiles, S);
+        assert_eq!(wfc.candidate_histogram(), BTreeMap::from([(1, 3), (2, 2), (3, 1)]));
+    }
+
+    #[test]
+    fn cells_with_candidate() {
+        let tiles = vec![
+            Tile::Definite(5), Tile::Indefinite(BTreeSet::from([1, 5])), Tile::Indefinite(BTreeSet::from([1, 2])),
+            Tile::Definite(1), Tile::Indefinite(BTreeSet::from([2, 5, 7])), Tile::Definite(5),
+        ];
+        let wfc = Wfc::new(3, 2, tiles, S);
+        assert_eq!(wfc.cells_with_candidate(&5, false), [1, 4]);
+        assert_eq!(wfc.cells_with_candidate(&5, true), [0, 1, 4, 5]);
+        assert_eq!(wfc.cells_with_candidate(&1, true), [1, 2, 3]);
+        assert_eq!(wfc.cells_with_candidate(&9, true), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn iter_definite() {
+        let tiles = (0..6)
+            .map(|idx| match idx % 2 {
+                0 => Tile::Definite(idx),
+                _ => Tile::Indefinite(BTreeSet::from([0, 1])),
+            })
+            .collect();
+        let wfc = Wfc::new(3, 2, tiles, S);
+        let definite = wfc.iter_definite().collect::<Vec<_>>();
+        assert_eq!(definite, [(0, 0, &0), (2, 0, &2), (1, 1, &4)]);
+    }
+
+    #[test]
+    fn journal_undo() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16];
+        let mut wfc = Wfc::new(4, 4, tiles, checkerboard());
+        let before = wfc.snapshot();
+
+        let mut journal = Vec::new();
+        journal.push((5, wfc.set(5, Tile::Definite(0))));
+        let seeds = wfc.rules.dependents(wfc.view(5));
+        wfc.propagate_journaled(seeds, &mut journal).unwrap();
+        assert!(journal.len() > 1);
+        assert_eq!(wfc.indefinite_count(), 0);
+
+        wfc.undo_journal(&mut journal, 0);
+        assert!(journal.is_empty());
+        assert_eq!(wfc.snapshot(), before);
+        assert_eq!(wfc.indefinite_count(), 16);
+    }
+
+    #[test]
+    fn constrain() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 16];
+        let mut wfc = Wfc::new(4, 4, tiles, Unforced);
+        wfc.constrain(|idx, state| idx % 4 >= 2 || *state != 2).unwrap();
+        for (x, _, tile) in wfc.iter() {
+            let expected = if x < 2 { BTreeSet::from([0, 1]) } else { BTreeSet::from([0, 1, 2]) };
+            assert_eq!(tile, &Tile::Indefinite(expected));
+        }
+
+        let before = wfc.snapshot();
+        assert_eq!(wfc.constrain(|idx, state| idx != 5 || *state == 2), Err(WfcError::Contradiction { idx: 5, x: 1, y: 1 }));
+        assert_eq!(wfc.snapshot(), before);
+
+        let mut strip = Wfc::new(3, 1, vec![Tile::Indefinite(BTreeSet::from([0, 1])); 3], checkerboard());
+        strip.constrain(|idx, state| idx != 0 || *state == 0).unwrap();
+        assert_eq!(strip.tiles(), [0, 1, 0].map(Tile::Definite));
+
+        // Both ends at 0 leaves no checkerboard for the middle of an even strip
+        let mut strip = Wfc::new(4, 1, vec![Tile::Indefinite(BTreeSet::from([0, 1])); 4], checkerboard());
+        let result = strip.constrain(|idx, state| (idx != 0 && idx != 3) || *state == 0);
+        assert_eq!(result, Err(WfcError::Contradiction { idx: 2, x: 2, y: 0 }));
+        assert_eq!(strip.indefinite_count(), 4);
+    }
+
+    #[test]
+    fn collapse_region() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 36];
+        let mut wfc = Wfc::new(6, 6, tiles, checkerboard());
+        let mut rng = StdRng::seed_from_u64(4);
+        while wfc.try_step_with(&mut rng).unwrap() {}
+        let before = wfc.map.clone();
+
+        wfc.collapse_region_with(2..4, 1..5, &mut rng).unwrap();
+        assert_eq!(wfc.map, before);
+        assert_eq!(wfc.indefinite_count, 0);
+
+        // Swapping a border tile leaves the region no consistent fill
+        wfc.map[0] = Tile::Definite(1 - before[0].as_definite());
+        let broken = wfc.map.clone();
+        assert!(wfc.collapse_region_with(0..6, 1..6, &mut rng).is_err());
+        assert_eq!(wfc.map, broken);
+    }
+
+    #[test]
+    fn collapse_region_keeps_frozen() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 36];
+        let mut wfc = Wfc::new(6, 6, tiles, checkerboard());
+        let mut rng = StdRng::seed_from_u64(4);
+        while wfc.try_step_with(&mut rng).unwrap() {}
+        let before = wfc.map.clone();
+
+        // The frozen given decides the checkerboard, so regenerating the whole map cannot flip it
+        wfc.freeze(14).unwrap();
+        for _ in 0..8 {
+            wfc.collapse_region_with(0..6, 0..6, &mut rng).unwrap();
+            assert_eq!(wfc.map, before);
+            assert!(wfc.is_frozen(14));
+        }
+    }
+
+    /// Allows the second tile anything but 1 after a 1, and always picks 1 when it can
+    #[derive(Debug)]
+    struct Trap;
+
+    impl WfcRules<i32> for Trap {
+        type Cache = ();
+
+        fn get_states(&self, map: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            match (map.self_index(), map.definite_in(Direction::West)) {
+                (1, Some(0)) => BTreeSet::from([0]),
+                (1, Some(_)) => BTreeSet::new(),
+                _ => BTreeSet::from([0, 1]),
+            }
+        }
+
+        fn state_weight(&self, state: &i32) -> f64 {
+            *state as f64
+        }
+    }
+
+    #[test]
+    fn step_result() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 2];
+        let mut wfc = Wfc::new(2, 1, tiles, Trap).with_selection(ScanlineSelection);
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(wfc.step_result_with(&mut rng), Ok(StepOutcome::Backtracked));
+        assert_eq!(wfc.map[0], Tile::Indefinite(BTreeSet::from([0])));
+        assert_eq!(wfc.step_result_with(&mut rng), Ok(StepOutcome::Collapsed { idx: 0, value: 0 }));
+        assert_eq!(wfc.step_result_with(&mut rng), Ok(StepOutcome::Done));
+
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([1])), Tile::Indefinite(BTreeSet::from([0, 1]))];
+        let mut wfc = Wfc::new(2, 1, tiles, Trap).with_selection(ScanlineSelection);
+        assert_eq!(wfc.step_result_with(&mut rng), Ok(StepOutcome::Contradiction { idx: 1 }));
+
+        let mut wfc = Wfc::new(2, 2, vec![Tile::Indefinite(BTreeSet::from([0, 1])); 4], NanEntropy);
+        assert_eq!(wfc.step_result_with(&mut rng), Err(WfcError::InvalidEntropy(0)));
+        assert_eq!(wfc.step(), None);
+    }
+
+    #[derive(Debug)]
+    struct Unforced;
+
+    impl WfcRules<i32> for Unforced {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0, 1, 2])
+        }
+    }
+
+    #[test]
+    fn run_bounded() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16];
+        let mut wfc = Wfc::new(4, 4, tiles.clone(), Unforced);
+        assert_eq!(wfc.run_bounded(5), Err(WfcError::StepLimit));
+        assert_eq!(wfc.definite_indices().count(), 5);
+
+        let mut wfc = Wfc::new(4, 4, tiles.clone(), Unforced);
+        assert_eq!(wfc.run_bounded(16), Ok(()));
+        assert_eq!(wfc.definite_indices().count(), 16);
+
+        // The limit is checked before stepping, so no steps are taken with a limit of zero
+        let mut wfc = Wfc::new(4, 4, tiles, Unforced);
+        assert_eq!(wfc.run_bounded(0), Err(WfcError::StepLimit));
+        assert_eq!(wfc.definite_indices().count(), 0);
+
+        let mut wfc = Wfc::new(4, 4, (0..16).map(Tile::Definite).collect(), Unforced);
+        assert_eq!(wfc.run_bounded(0), Ok(()));
+    }
+
+    #[test]
+    fn run_bounded_never_forced() {
+        // Unforced never narrows a tile, so every step makes progress on exactly one tile and
+        // a large map keeps stepping long past the limit
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 40 * 40];
+        let mut wfc = Wfc::new(40, 40, tiles, Unforced);
+        assert_eq!(wfc.run_bounded(50), Err(WfcError::StepLimit));
+        assert_eq!(wfc.definite_indices().count(), 50);
+        assert_eq!(wfc.indefinite_indices().count(), 40 * 40 - 50);
+        assert_eq!(wfc.try_step(), Ok(true));
+    }
+
+    #[test]
+    fn tile_collapse() {
+        let mut tile = Tile::Indefinite(BTreeSet::from([0, 1, 2]));
+        let remaining = tile.collapse(&mut thread_rng()).unwrap();
+        assert_eq!(remaining.len(), 2);
+
+        let state = tile.as_definite();
+        assert!(!remaining.contains(state));
+        assert!([0, 1, 2].contains(state));
+
+        assert_eq!(tile.collapse(&mut thread_rng()), None);
+        assert_eq!(Tile::<i32>::Indefinite(BTreeSet::new()).collapse(&mut thread_rng()), None);
+    }
+
+    #[test]
+    fn ban() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 4];
+        let mut wfc = Wfc::new(2, 2, tiles, Unforced);
+        assert_eq!(wfc.ban(0, &0), Ok(()));
+        assert_eq!(wfc.map[0], Tile::Indefinite(BTreeSet::from([1, 2])));
+        assert_eq!(wfc.ban(0, &2), Ok(()));
+        assert_eq!(wfc.map[0], Tile::Definite(1));
+        assert_eq!(wfc.ban(0, &1), Err(WfcError::AlreadyDefinite(0)));
+
+        assert_eq!(wfc.ban(1, &0), Ok(()));
+        assert_eq!(wfc.ban(1, &1), Ok(()));
+        assert_eq!(wfc.map[1], Tile::Definite(2));
+    }
+
+    #[test]
+    fn ban_last_state() {
+        let mut wfc = Wfc::new(1, 1, vec![Tile::Indefinite(BTreeSet::from([0]))], Unforced);
+        assert_eq!(wfc.ban(0, &0), Err(WfcError::Contradiction { idx: 0, x: 0, y: 0 }));
+        assert_eq!(wfc.map[0], Tile::Indefinite(BTreeSet::from([0])));
+    }
+
+    #[test]
+    fn replay() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 4];
+        let mut wfc = Wfc::new(2, 2, tiles.clone(), checkerboard());
+        assert_eq!(wfc.run(), Ok(()));
+        // The first choice forces the rest of the board
+        assert_eq!(wfc.choice_log().len(), 1);
+
+        let mut replayed = Wfc::new(2, 2, tiles, checkerboard());
+        assert_eq!(replayed.replay(wfc.choice_log()), Ok(()));
+        assert_eq!(replayed.map, wfc.map);
+        assert_eq!(replayed.choice_log(), wfc.choice_log());
+    }
+
+    #[test]
+    fn render() {
+        let tiles = vec![
+            Tile::Definite(1),
+            Tile::Indefinite(BTreeSet::from([0, 1])),
+            Tile::Definite(0),
+            Tile::Definite(1),
+        ];
+        let wfc = Wfc::new(2, 2, tiles, S);
+        let rendered = wfc.render(|tile| match tile {
+            Tile::Definite(0) => ' ',
+            Tile::Definite(_) => '#',
+            Tile::Indefinite(_) => '.',
+        });
+        assert_eq!(rendered, "#.\n #");
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_propagation() {
+        let mut tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 64];
+        tiles[0] = Tile::Definite(0);
+        tiles[27] = Tile::Definite(1);
+        let wfc = Wfc::new(8, 8, tiles, checkerboard());
+
+        let indices = wfc.indefinite_indices().collect::<Vec<_>>();
+        assert_eq!(wfc.derive_states_parallel(&indices), wfc.derive_states_sequential(&indices, &mut ()));
+    }
+
+    #[test]
+    #[cfg(feature = "parallel")]
+    fn parallel_run_matches_sequential() {
+        // Neighbors differ by at most one, which leaves choices for the seed to decide
+        let mut rules = AdjacencyRules::new();
+        for a in 0..4 {
+            for b in (a - 1).max(0)..(a + 2).min(4) {
+                rules.allow(a, Direction::East, b);
+                rules.allow(a, Direction::South, b);
+            }
+        }
+        let run = |min_batch: usize| {
+            let tiles = vec![Tile::Indefinite(rules.states().clone()); 256];
+            let mut wfc = Wfc::new(16, 16, tiles, rules.clone()).with_parallel_min_batch(min_batch);
+            let mut rng = StdRng::seed_from_u64(4);
+            let result = wfc.run_with_backtracking_with(&mut rng);
+            (result, wfc.into_partial())
+        };
+
+        let sequential = run(usize::MAX);
+        assert_eq!(sequential.0, Ok(()));
+        assert_eq!(run(0), sequential);
+        assert_eq!(run(crate::wfc::PARALLEL_MIN_BATCH), sequential);
+    }
+
+    #[test]
+    fn iter() {
+        let wfc = Wfc::new(3, 2, (0..6).map(Tile::Definite).collect(), S);
+        let coords = wfc.iter()
+            .map(|(x, y, _)| (x, y))
+            .collect::<Vec<_>>();
+        assert_eq!(coords, vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+        assert_eq!(wfc.iter().count(), wfc.width() * wfc.height());
+        assert!(wfc.iter().all(|(x, y, tile)| tile == &Tile::Definite(wfc.xy_pair(x, y) as i32)));
+    }
+
+    #[test]
+    fn to_rgb() {
+        let wfc = wfc();
+        let pixels = wfc.to_rgb(|state| [*state as u8, 0, 255]).unwrap();
+        assert_eq!(pixels.len(), 4 * 4 * 3);
+        let idx = wfc.xy_pair(2, 1) * 3;
+        assert_eq!(pixels[idx..idx + 3], [6, 0, 255]);
+
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[5] = Tile::Indefinite(BTreeSet::from([5]));
+        let wfc = Wfc::new(4, 4, tiles, S);
+        assert_eq!(wfc.to_rgb(|_| [0; 3]), Err(WfcError::NotCollapsed(5)));
+    }
+
+    #[test]
+    fn propagation_cascades() {
+        let mut rules = AdjacencyRules::new();
+        for (a, b) in [(0, 1), (1, 2), (2, 3)] {
+            rules.allow(a, Direction::East, b);
+        }
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2, 3])); 4];
+        let mut wfc = Wfc::new(4, 1, tiles, rules);
+
+        // Each tile only learns its state from the one next to it
+        assert_eq!(wfc.collapse_at(0, 0), Ok(()));
+        assert_eq!(wfc.map, (0..4).map(Tile::Definite).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn propagation_rolls_back() {
+        let mut rules = AdjacencyRules::new();
+        rules.allow(0, Direction::East, 1);
+        rules.allow(1, Direction::East, 2);
+        let mut tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 4];
+        tiles[3] = Tile::Definite(0);
+        let mut wfc = Wfc::new(4, 1, tiles, rules);
+        let before = wfc.map.clone();
+
+        assert_eq!(wfc.collapse_at(0, 0), Err(WfcError::Contradiction { idx: 2, x: 2, y: 0 }));
+        assert_eq!(wfc.map, before);
+    }
+
+    #[test]
+    fn contradictions() {
+        let tiles = vec![
+            Tile::Definite(0),
+            Tile::Indefinite(BTreeSet::new()),
+            Tile::Indefinite(BTreeSet::from([0, 1])),
+            Tile::Indefinite(BTreeSet::new()),
+        ];
+        let impossible = Wfc::new(2, 2, tiles, S);
+        assert_eq!(impossible.contradictions(), vec![1, 3]);
+        assert_eq!(wfc().contradictions(), vec![]);
+    }
+
+    #[test]
+    fn neighbors_of() {
+        let wfc = wfc();
+        assert_eq!(wfc.neighbors_of(0), vec![1, 4]);
+        assert_eq!(wfc.neighbors_of(15), vec![11, 14]);
+        assert_eq!(wfc.neighbors_of(4), vec![0, 5, 8]);
+        assert_eq!(wfc.neighbors_of(7), vec![3, 11, 6]);
+        assert_eq!(wfc.neighbors_of(5), vec![1, 6, 9, 4]);
+    }
+
+    #[test]
+    fn spans_without_view() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+        let rows = |span: Span<'_, i32>| span.row_iter().cloned().collect::<Vec<_>>();
+        assert_eq!(rows(wfc.row(1)), rows(view.row_at(1)));
+        assert_eq!(rows(wfc.col(2)), rows(view.col_at(2)));
+        assert_eq!(rows(wfc.span(1..3, 2..4)), rows(view.span(1..3, 2..4)));
+        assert_eq!(rows(wfc.row(1)), (4..8).map(Tile::Definite).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn hex_neighbors() {
+        let pointy = wfc().with_topology(Topology::HexPointy);
+        // (1, 2) on an even row and (1, 1) on an odd row
+        assert_eq!(pointy.neighbors_of(9), vec![5, 10, 13, 12, 8, 4]);
+        assert_eq!(pointy.neighbors_of(5), vec![2, 6, 10, 9, 4, 1]);
+        assert_eq!(pointy.neighbors_of(4), vec![1, 5, 9, 8, 0]);
+
+        let flat = wfc().with_topology(Topology::HexFlat);
+        // (2, 1) on an even column and (1, 1) on an odd column
+        assert_eq!(flat.neighbors_of(6), vec![2, 3, 7, 10, 5, 1]);
+        assert_eq!(flat.neighbors_of(5), vec![1, 6, 10, 9, 8, 4]);
+        assert_eq!(flat.neighbors_of(0), vec![1, 4]);
+    }
+
+    #[test]
+    fn shannon_entropy_weights() {
+        // ln 6 - (1 ln 1 + 2 ln 2 + 3 ln 3) / 6
+        assert!((shannon_entropy(&[1.0, 2.0, 3.0]) - 1.011404).abs() < 1e-6);
+        assert!((shannon_entropy(&[1.0, 1.0, 1.0]) - 3f64.ln()).abs() < 1e-12);
+        assert_eq!(shannon_entropy(&[4.0]), 0.0);
+        assert_eq!(shannon_entropy(&[]), 0.0);
+
+        let exact = shannon_entropy(&[1.0, 2.0, 3.0]);
+        let noisy = noisy_shannon_entropy(&[1.0, 2.0, 3.0], &mut thread_rng());
+        assert!(noisy >= exact && noisy < exact + ENTROPY_NOISE);
+    }
+
+    #[test]
+    fn try_step_with() {
+        let generate = |seed| {
+            let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 16];
+            let mut wfc = Wfc::new(4, 4, tiles, Unforced);
+            let mut rng = StdRng::seed_from_u64(seed);
+            while wfc.try_step_with(&mut rng).unwrap() {}
+            wfc.map
+        };
+        assert_eq!(generate(7), generate(7));
+        assert!(generate(7).iter().all(|tile| matches!(tile, Tile::Definite(_))));
+    }
+
+    #[test]
+    fn entropy_ranking() {
+        let tiles = vec![
+            Tile::Definite(0),
+            Tile::Indefinite(BTreeSet::from([0, 1, 2])),
+            Tile::Indefinite(BTreeSet::from([0, 1])),
+            Tile::Indefinite(BTreeSet::from([0, 1, 2, 3])),
+            Tile::Indefinite(BTreeSet::from([1, 2])),
+        ];
+        let wfc = Wfc::new(5, 1, tiles, AdjacencyRules::new());
+        assert_eq!(wfc.entropy_ranking(), vec![(2, 2.0), (4, 2.0), (1, 3.0), (3, 4.0)]);
+    }
+
+    #[test]
+    fn only_lowest_entropy_selected() {
+        let two = Tile::Indefinite(BTreeSet::from([0, 1]));
+        let three = Tile::Indefinite(BTreeSet::from([0, 1, 2]));
+        let tiles = vec![three.clone(), two.clone(), three, two.clone(), two];
+
+        let mut chosen = BTreeSet::new();
+        for _ in 0..64 {
+            let mut wfc = Wfc::new(5, 1, tiles.clone(), Counted);
+            chosen.insert(wfc.step_diff().unwrap().unwrap().collapsed);
+        }
+        assert!(chosen.is_subset(&BTreeSet::from([1, 3, 4])), "{:?}", chosen);
+        assert!(chosen.len() > 1, "ties should be broken randomly");
+    }
+
+    #[test]
+    fn new_seeded_density() {
+        let states = BTreeSet::from([0, 1, 2]);
+        let empty = Wfc::new_seeded_density(4, 4, states.clone(), Counted, 1, 0.0).unwrap();
+        assert_eq!(empty.definite_indices().count(), 0);
+
+        let full = Wfc::new_seeded_density(4, 4, states.clone(), Counted, 1, 1.0).unwrap();
+        assert_eq!(full.indefinite_indices().count(), 0);
+
+        let clamped = Wfc::new_seeded_density(4, 4, states.clone(), Counted, 1, 7.0).unwrap();
+        assert_eq!(clamped.indefinite_indices().count(), 0);
+
+        let a = Wfc::new_seeded_density(8, 8, states.clone(), Counted, 3, 0.5).unwrap();
+        let b = Wfc::new_seeded_density(8, 8, states, Counted, 3, 0.5).unwrap();
+        assert_eq!(a.map, b.map);
+    }
+
+    #[test]
+    fn collapse_weighted() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut ones = 0;
+        for _ in 0..1000 {
+            let mut tile = Tile::Indefinite(BTreeSet::from([0, 1, 2]));
+            let weight = |state: &i32| [0.0, 3.0, 1.0][*state as usize];
+            assert!(tile.collapse_weighted(&mut rng, weight).is_some());
+            assert_ne!(tile, Tile::Definite(0));
+            ones += (tile == Tile::Definite(1)) as usize;
+        }
+        assert!((700..800).contains(&ones), "{}", ones);
+
+        let mut tile = Tile::Indefinite(BTreeSet::from([0, 1]));
+        assert!(tile.collapse_weighted(&mut rng, |_| 0.0).is_some());
+        assert!(tile.is_definite());
+    }
+
+    #[test]
+    fn definite_or() {
+        assert_eq!(Tile::Definite(3).definite_or(&0), &3);
+        assert_eq!(Tile::Indefinite(BTreeSet::from([1, 2])).definite_or(&0), &0);
+    }
+
+    #[test]
+    fn candidates() {
+        let definite = Tile::Definite(3);
+        assert!(matches!(definite.candidates(), Cow::Owned(states) if states == BTreeSet::from([3])));
+        let indefinite = Tile::Indefinite(BTreeSet::from([1, 2]));
+        assert!(matches!(indefinite.candidates(), Cow::Borrowed(states) if states == &BTreeSet::from([1, 2])));
+        assert!(Tile::<i32>::Indefinite(BTreeSet::new()).candidates().is_empty());
+    }
+
+    #[test]
+    fn replace_if_changed() {
+        let mut tile = Tile::Indefinite(BTreeSet::from([0, 1, 2]));
+        assert!(!tile.replace_if_changed(Tile::Indefinite(BTreeSet::from([0, 1, 2]))));
+        assert!(tile.replace_if_changed(Tile::Indefinite(BTreeSet::from([0, 2]))));
+        assert_eq!(tile, Tile::Indefinite(BTreeSet::from([0, 2])));
+        assert!(tile.replace_if_changed(Tile::Definite(2)));
+        assert!(!tile.replace_if_changed(Tile::Definite(2)));
+    }
+
+    /// Colors the map so no two neighbors match, counting how often it works that out
+    #[derive(Debug, Default)]
+    struct Coloring {
+        computed: AtomicUsize,
+        cached: bool,
+    }
+
+    impl WfcRules<i32> for Coloring {
+        /// The states allowed for each arrangement of definite neighbors
+        type Cache = BTreeMap<Vec<Option<i32>>, BTreeSet<i32>>;
+
+        fn get_states(&self, map: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            self.computed.fetch_add(1, Ordering::Relaxed);
+            let mut states = BTreeSet::from([0, 1, 2]);
+            for dir in Direction::ALL {
+                if let Some(state) = map.definite_in(dir) {
+                    states.remove(state);
+                }
+            }
+            states
+        }
+
+        fn get_states_cached(&self, map: WfcView<'_, i32, Self>, cache: &mut Self::Cache) -> BTreeSet<i32> {
+            if !self.cached {
+                return self.get_states(map);
+            }
+            let key = Direction::ALL.map(|dir| map.definite_in(dir).copied()).to_vec();
+            cache.entry(key)
+                .or_insert_with(|| self.get_states(map))
+                .clone()
+        }
+    }
+
+    #[test]
+    fn rules_cache() {
+        let generate = |cached| {
+            let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 36];
+            let rules = Coloring { cached, ..Coloring::default() };
+            let mut wfc = Wfc::new(6, 6, tiles, rules).with_selection(ScanlineSelection);
+            let mut rng = StdRng::seed_from_u64(11);
+            while wfc.try_step_with(&mut rng).unwrap() {}
+            (wfc.map, wfc.rules.computed.into_inner())
+        };
+        let (uncached, uncached_computed) = generate(false);
+        let (cached, cached_computed) = generate(true);
+        assert_eq!(cached, uncached);
+        assert!(cached_computed * 4 < uncached_computed, "{} vs {}", cached_computed, uncached_computed);
+    }
+
+    /// Every tile must differ from every other, with each tile listing its dependents backwards
+    #[derive(Debug)]
+    struct AllDifferent;
+
+    impl WfcRules<i32> for AllDifferent {
+        type Cache = ();
+
+        fn get_states(&self, map: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            let mut states = BTreeSet::from([0, 1]);
+            for tile in map.wfc.tiles() {
+                if let Tile::Definite(state) = tile {
+                    states.remove(state);
+                }
+            }
+            states
+        }
+
+        fn dependents(&self, map: WfcView<'_, i32, Self>) -> Vec<usize> {
+            let idx = map.self_index();
+            (0..map.wfc.len()).rev().filter(|other| *other != idx).collect()
+        }
+    }
+
+    #[test]
+    fn propagation_order() {
+        for seed in 0..8 {
+            let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 3];
+            let mut wfc = Wfc::new(3, 1, tiles, AllDifferent);
+            // Tiles 1 and 2 are both forced to the same state, and tile 1 is always applied first
+            let result = wfc.step_at_with(0, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(result, Err(WfcError::Contradiction { idx: 2, x: 2, y: 0 }));
+        }
+    }
+
+    #[test]
+    fn propagation_radius() {
+        let strip = || vec![Tile::Indefinite(BTreeSet::from([0, 1])); 5];
+        let mut local = Wfc::new(5, 1, strip(), checkerboard()).with_propagation_radius(Some(1));
+        local.collapse_at(0, 0).unwrap();
+        assert_eq!(local[1], Tile::Definite(1));
+        assert_eq!(local.indefinite_indices().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        let mut global = Wfc::new(5, 1, strip(), checkerboard());
+        global.collapse_at(0, 0).unwrap();
+        assert!(global.is_collapsed());
+    }
+
+    #[test]
+    fn tiles() {
+        let wfc = wfc();
+        assert_eq!(wfc.tiles().len(), wfc.width() * wfc.height());
+        assert_eq!(wfc.tiles()[6], wfc[6]);
+    }
+
+    #[test]
+    fn index() {
+        let wfc = wfc();
+        assert_eq!(&wfc[5], wfc.view(5).get());
+        assert_eq!(wfc[15], Tile::Definite(15));
+        assert_eq!(wfc.len(), 16);
+        assert!(!wfc.is_empty());
+    }
+
+    /// Counts how often the entropy of a tile is asked for
+    #[derive(Debug, Default)]
+    struct EntropyCounted(AtomicUsize);
+
+    impl WfcRules<i32> for EntropyCounted {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0, 1])
+        }
+
+        fn entropy(&self, _: &Tile<i32>) -> f64 {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            0.0
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 16];
+        let mut wfc = Wfc::new(4, 4, tiles, Coloring::default());
+        let scanned = |wfc: &Wfc<i32, Coloring>| wfc.indefinite_indices().count();
+        assert_eq!((wfc.indefinite_count(), wfc.progress()), (16, 0.0));
+
+        let snap = wfc.snapshot();
+        wfc.collapse_at(5, 0).unwrap();
+        assert_eq!(wfc.indefinite_count(), scanned(&wfc));
+        wfc.ban(0, &1).unwrap();
+        wfc.ban(0, &2).unwrap();
+        assert!(wfc[0].is_definite());
+        assert_eq!(wfc.indefinite_count(), scanned(&wfc));
+        assert_eq!(wfc.collapse_at(1, 0), Err(WfcError::Contradiction { idx: 1, x: 1, y: 0 }));
+        wfc.step_at_with(10, &mut StdRng::seed_from_u64(2)).unwrap();
+        assert_eq!(wfc.indefinite_count(), scanned(&wfc));
+        assert!(wfc.progress() > 0.0 && !wfc.is_collapsed());
+
+        wfc.restore(snap);
+        assert_eq!(wfc.indefinite_count(), 16);
+        wfc.run_with_backtracking_with(&mut StdRng::seed_from_u64(2)).unwrap();
+        assert_eq!(wfc.indefinite_count(), scanned(&wfc));
+        assert!(wfc.is_collapsed());
+        assert_eq!(wfc.progress(), 1.0);
+    }
+
+    #[test]
+    fn noise_seed() {
+        let order = |noise_seed, seed| {
+            let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16];
+            let mut wfc = Wfc::new(4, 4, tiles, Unforced).with_noise_seed(noise_seed);
+            let mut rng = StdRng::seed_from_u64(seed);
+            while wfc.try_step_with(&mut rng).unwrap() {}
+            wfc.choice_log().iter().map(|(idx, _)| *idx).collect::<Vec<_>>()
+        };
+        assert_eq!(order(7, 1), order(7, 2));
+        assert_ne!(order(7, 1), order(8, 1));
+
+        let wfc = Wfc::new(4, 4, vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16], Unforced);
+        let ranking = wfc.with_noise_seed(7).entropy_ranking();
+        assert!(ranking.iter().all(|(_, entropy)| (0.0..ENTROPY_NOISE).contains(entropy)));
+    }
+
+    #[test]
+    fn generate() {
+        let all_states = BTreeSet::from([0, 1, 2]);
+        let mut wfc = Wfc::new(8, 8, vec![Tile::Definite(0); 64], Coloring::default());
+        // The first attempt from seed 10 paints itself into a corner
+        assert!(matches!(wfc.generate(all_states.clone(), 1, 10), Err(WfcError::Contradiction { .. })));
+        assert_eq!(wfc.generate(all_states.clone(), 0, 10), Err(WfcError::StepLimit));
+
+        assert_eq!(wfc.generate(all_states.clone(), 3, 10), Ok(()));
+        assert!(wfc.is_collapsed());
+        for idx in 0..64 {
+            for neighbor in wfc.neighbors_of(idx) {
+                assert_ne!(wfc[idx], wfc[neighbor]);
+            }
+        }
+        let map = wfc.map.clone();
+        wfc.generate(all_states, 3, 10).unwrap();
+        assert_eq!(wfc.map, map);
+    }
+
+    #[test]
+    fn into_partial() {
+        let mut wfc = Wfc::new(8, 8, vec![Tile::Definite(0); 64], Coloring::default());
+        // Seed 10 runs into a contradiction partway through, as in the generate test
+        assert!(wfc.generate(BTreeSet::from([0, 1, 2]), 1, 10).is_err());
+        let partial = wfc.into_partial();
+        assert_eq!(partial.len(), 64);
+        let definite = partial.iter().filter(|tile| tile.is_definite()).count();
+        assert!(definite > 0 && definite < 64, "{}", definite);
+    }
+
+    #[test]
+    fn indefinite_count() {
+        let tiles = (0..16).map(Tile::Definite).collect();
+        let mut wfc = Wfc::new(4, 4, tiles, EntropyCounted::default());
+        assert_eq!(wfc.try_step_with(&mut StdRng::seed_from_u64(0)), Ok(false));
+        assert_eq!(wfc.rules.0.load(Ordering::Relaxed), 0);
+
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2])); 36];
+        let mut wfc = Wfc::new(6, 6, tiles, Coloring::default());
+        let mut rng = StdRng::seed_from_u64(3);
+        while wfc.try_step_with(&mut rng).unwrap() {
+            assert_eq!(wfc.indefinite_count, wfc.indefinite_indices().count());
+        }
+        assert_eq!(wfc.indefinite_count, 0);
+    }
+}
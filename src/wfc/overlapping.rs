@@ -0,0 +1,252 @@
+//! The "overlapping model", which learns its rules from an example grid
+
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use alloc::collections::BTreeSet;
+#[cfg(feature = "std")]
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::hash::Hash;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use super::State;
+use super::Direction;
+#[cfg(feature = "std")]
+use super::{AdjacencyRules, Tile, WfcRules, WfcView};
+
+/// Returns every [n]x[n] pattern in [sample] along with how often it occurs
+///
+/// Patterns are stored row-major and only taken from windows fully inside the sample
+///
+/// # Panics
+/// * If [sample] is empty or its rows are not all the same length
+/// * If [n] is zero or larger than the sample
+pub fn patterns<T: State>(sample: &[Vec<T>], n: usize) -> BTreeMap<Vec<T>, usize> {
+    assert!(!sample.is_empty(), "sample cannot be empty");
+    let width = sample[0].len();
+    assert!(sample.iter().all(|row| row.len() == width), "sample rows must all be the same length");
+    assert!(n > 0, "n cannot be zero");
+    assert!(n <= width && n <= sample.len(), "n must fit inside the sample");
+
+    let mut patterns = BTreeMap::new();
+    for y in 0..=sample.len() - n {
+        for x in 0..=width - n {
+            let pattern = sample[y..y + n]
+                .iter()
+                .flat_map(|row| row[x..x + n].iter().cloned())
+                .collect::<Vec<_>>();
+            *patterns.entry(pattern).or_insert(0) += 1;
+        }
+    }
+    patterns
+}
+
+/// An [n]x[n] window of a sample, stored row-major, used as a state by the overlapping model
+///
+/// A map of patterns shows each pattern's top-left tile, see [Pattern::value]
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Pattern<T: State>(Vec<T>);
+
+impl<T: State> State for Pattern<T> {}
+
+impl<T: State> Pattern<T> {
+    /// Returns the width and height of the pattern
+    pub fn size(&self) -> usize {
+        self.0.len().isqrt()
+    }
+
+    /// Returns the tiles of the pattern, row-major
+    pub fn tiles(&self) -> &[T] {
+        &self.0
+    }
+
+    /// Returns the top-left tile of the pattern, which is what a map shows where it is placed
+    pub fn value(&self) -> &T {
+        &self.0[0]
+    }
+
+    /// Returns whether [other] may be placed one tile in [dir] of this pattern, which it can
+    /// when the two agree on every tile where they overlap
+    pub fn overlaps(&self, dir: Direction, other: &Pattern<T>) -> bool {
+        let n = self.size() as isize;
+        let (dx, dy) = dir.offset();
+        (0..n).all(|y| (0..n).all(|x| {
+            let (ox, oy) = (x - dx, y - dy);
+            !(0..n).contains(&ox) || !(0..n).contains(&oy)
+                || self.0[(y * n + x) as usize] == other.0[(oy * n + ox) as usize]
+        }))
+    }
+}
+
+/// Learns the overlapping model of [sample], whose states are its [n]x[n] patterns
+///
+/// Two patterns may be neighbors in a direction if they agree where they overlap when one is
+/// shifted a tile that way. Each pattern's frequency is the share of the sample's windows it
+/// fills, so they sum to 1 and can be given to [FrequencyRules] to make generation match them
+///
+/// # Panics
+/// * In any of the cases [patterns] panics
+#[cfg(feature = "std")]
+#[allow(clippy::type_complexity)]
+pub fn learn<T: State + Hash>(
+    sample: &[Vec<T>],
+    n: usize,
+) -> (BTreeSet<Pattern<T>>, AdjacencyRules<Pattern<T>>, HashMap<Pattern<T>, f64>) {
+    let counts = patterns(sample, n);
+    let windows = counts.values().sum::<usize>() as f64;
+    let frequencies = counts.into_iter()
+        .map(|(pattern, count)| (Pattern(pattern), count as f64 / windows))
+        .collect::<HashMap<_, _>>();
+
+    let mut rules = AdjacencyRules::new();
+    for a in frequencies.keys() {
+        for b in frequencies.keys() {
+            for dir in [Direction::East, Direction::South] {
+                if a.overlaps(dir, b) {
+                    rules.allow(a.clone(), dir, b.clone());
+                }
+            }
+        }
+    }
+    (frequencies.keys().cloned().collect(), rules, frequencies)
+}
+
+/// [AdjacencyRules] that collapse tiles to each state in proportion to its frequency, as
+/// returned by [learn]
+///
+/// States missing from the frequencies are never chosen while another state is possible
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct FrequencyRules<T: State + Hash> {
+    rules: AdjacencyRules<T>,
+    frequencies: HashMap<T, f64>,
+}
+
+#[cfg(feature = "std")]
+impl<T: State + Hash> FrequencyRules<T> {
+    /// Creates rules that place states by [rules], weighted by [frequencies]
+    pub fn new(rules: AdjacencyRules<T>, frequencies: HashMap<T, f64>) -> Self {
+        Self { rules, frequencies }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: State + Hash> WfcRules<T> for FrequencyRules<T> {
+    type Cache = ();
+
+    fn get_states(&self, map: WfcView<'_, T, Self>) -> BTreeSet<T> {
+        self.rules.states_at(&map)
+    }
+
+    fn entropy(&self, tile: &Tile<T>) -> f64 {
+        self.rules.entropy(tile)
+    }
+
+    fn dependents(&self, map: WfcView<'_, T, Self>) -> Vec<usize> {
+        map.neighbors()
+    }
+
+    fn state_weight(&self, state: &T) -> f64 {
+        self.frequencies.get(state).copied().unwrap_or(0.0)
+    }
+
+    fn validate(&self, all_states: &BTreeSet<T>) -> Result<(), String> {
+        self.rules.validate(all_states)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::wfc::{Direction, Tile, Wfc, WfcRules};
+
+    use super::{FrequencyRules, learn, Pattern, patterns};
+
+    fn checkerboard(size: usize) -> Vec<Vec<char>> {
+        (0..size)
+            .map(|y| (0..size).map(|x| if (x + y) % 2 == 0 { 'B' } else { 'W' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn pattern_frequencies() {
+        let patterns = patterns(&checkerboard(3), 2);
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[&vec!['B', 'W', 'W', 'B']], 2);
+        assert_eq!(patterns[&vec!['W', 'B', 'B', 'W']], 2);
+    }
+
+    #[test]
+    fn pattern_overlaps() {
+        let a = Pattern("ABCD".chars().collect());
+        assert_eq!(a.size(), 2);
+        assert_eq!(a.value(), &'A');
+        assert!(a.overlaps(Direction::East, &Pattern("BXDY".chars().collect())));
+        assert!(a.overlaps(Direction::South, &Pattern("CDXY".chars().collect())));
+        assert!(a.overlaps(Direction::West, &Pattern("XAYC".chars().collect())));
+        assert!(a.overlaps(Direction::North, &Pattern("XYAB".chars().collect())));
+        assert!(!a.overlaps(Direction::East, &a));
+    }
+
+    #[test]
+    fn learn_checkerboard() {
+        for _ in 0..16 {
+            let (states, rules, _) = learn(&checkerboard(3), 2);
+            let mut wfc = Wfc::new(2, 2, vec![Tile::Indefinite(states); 4], rules);
+            while wfc.step().is_some() {}
+
+            let map = wfc.map.iter()
+                .map(|tile| *tile.as_definite().value())
+                .collect::<Vec<_>>();
+            assert!(map == ['B', 'W', 'W', 'B'] || map == ['W', 'B', 'B', 'W'], "{:?}", map);
+        }
+    }
+
+    #[test]
+    fn learn_larger_patterns() {
+        let (states, rules, frequencies) = learn(&checkerboard(4), 3);
+        assert_eq!(states.len(), 2);
+        assert!(states.iter().all(|pattern| pattern.tiles().len() == 9));
+        assert!(frequencies.values().all(|frequency| *frequency == 0.5));
+        for pattern in &states {
+            let east = rules.allowed(pattern, Direction::East).unwrap();
+            assert_eq!(east.len(), 1);
+            assert_ne!(east.first().unwrap().value(), pattern.value());
+        }
+    }
+
+    #[test]
+    fn learned_frequencies() {
+        let sample = ["GGWW", "GGGW", "WGGG", "GGGG"].map(|row| row.chars().collect::<Vec<_>>());
+        // 1x1 patterns may be placed next to anything, so only the frequencies shape the map
+        let (states, rules, frequencies) = learn(&sample, 1);
+        assert_eq!(frequencies[&Pattern(vec!['G'])], 0.75);
+        assert_eq!(frequencies[&Pattern(vec!['W'])], 0.25);
+
+        let ratio = grass_ratio(&states, FrequencyRules::new(rules.clone(), frequencies));
+        assert!((0.7..0.8).contains(&ratio), "{}", ratio);
+
+        // Without the frequencies each pattern is as likely as the other
+        let ratio = grass_ratio(&states, rules);
+        assert!((0.45..0.55).contains(&ratio), "{}", ratio);
+    }
+
+    /// Returns the share of grass in 8x8 maps of [states] generated by [rules] over many seeds
+    fn grass_ratio<R: WfcRules<Pattern<char>> + Clone>(states: &BTreeSet<Pattern<char>>, rules: R) -> f64 {
+        let mut grass = 0;
+        for seed in 0..32 {
+            let tiles = vec![Tile::Indefinite(states.clone()); 64];
+            let mut wfc = Wfc::new(8, 8, tiles, rules.clone());
+            let mut rng = StdRng::seed_from_u64(seed);
+            while wfc.try_step_with(&mut rng).unwrap() {}
+            grass += wfc.map.iter().filter(|tile| tile.as_definite().value() == &'G').count();
+        }
+        grass as f64 / (32 * 64) as f64
+    }
+}
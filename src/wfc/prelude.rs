@@ -0,0 +1,29 @@
+//! The types and traits needed by most uses of the crate, so `use wfc::prelude::*;` is enough
+
+// The crate's own binary only uses part of the prelude
+#[allow(unused_imports)]
+pub use super::{
+    AdjacencyRules, Direction, ScanlineSelection, SelectionStrategy, Span, State, Tile, Topology,
+    Wfc, WfcBuilder, WfcError, WfcRules, WfcView,
+};
+
+#[cfg(test)]
+mod test {
+    use crate::wfc::prelude::*;
+
+    #[test]
+    fn prelude_only() {
+        let mut rules = AdjacencyRules::new();
+        for dir in [Direction::East, Direction::South] {
+            rules.allow(0, dir, 1);
+            rules.allow(1, dir, 0);
+        }
+        let tiles = vec![Tile::Indefinite(rules.states().clone()); 4];
+        let mut wfc = Wfc::new(2, 2, tiles, rules).with_selection(ScanlineSelection);
+        assert_eq!(wfc.run(), Ok(()));
+
+        let span: Span<'_, i32> = wfc.view(0).row();
+        assert_eq!(span.definite_iter().count(), 2);
+        assert_eq!(wfc.step_at(0), Err(WfcError::AlreadyDefinite(0)));
+    }
+}
@@ -0,0 +1,296 @@
+//! Ready-made rules for common kinds of map
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use super::{Direction, Span, State, Tile, WfcRules, WfcView};
+
+/// Rules where no tile may be the same state as a tile orthogonally next to it, like coloring a
+/// map so no two bordering regions match
+#[derive(Debug, Clone)]
+pub struct NoAdjacentEqualRules<T: State> {
+    states: BTreeSet<T>,
+}
+
+impl<T: State> NoAdjacentEqualRules<T> {
+    /// Creates the rules for a map whose tiles may be any of [states]
+    pub fn new(states: BTreeSet<T>) -> Self {
+        Self { states }
+    }
+
+    /// Returns every state a tile may be
+    pub fn states(&self) -> &BTreeSet<T> {
+        &self.states
+    }
+}
+
+impl<T: State> WfcRules<T> for NoAdjacentEqualRules<T> {
+    type Cache = ();
+
+    fn get_states(&self, map: WfcView<'_, T, Self>) -> BTreeSet<T> {
+        let mut states = self.states.clone();
+        for idx in map.neighbors() {
+            if let Tile::Definite(state) = &map.wfc[idx] {
+                states.remove(state);
+            }
+        }
+        states
+    }
+
+    fn entropy(&self, tile: &Tile<T>) -> f64 {
+        match tile {
+            Tile::Definite(_) => 0.0,
+            Tile::Indefinite(states) => states.len() as f64,
+        }
+    }
+
+    fn dependents(&self, map: WfcView<'_, T, Self>) -> Vec<usize> {
+        map.neighbors()
+    }
+}
+
+/// Rules that allow any state everywhere, but resolve the tiles agreeing with their neighbors
+/// first so regions of one state grow together
+///
+/// A tile's entropy is how many of its states its 8 neighbors could also be, divided by one more
+/// than the number of definite neighbors it could match. Other rules can return its [entropy_at]
+/// from their own to smooth a map that is otherwise constrained
+#[derive(Debug, Clone)]
+pub struct SmoothingRules<T: State> {
+    states: BTreeSet<T>,
+}
+
+impl<T: State> SmoothingRules<T> {
+    /// Creates the rules for a map whose tiles may be any of [states]
+    pub fn new(states: BTreeSet<T>) -> Self {
+        Self { states }
+    }
+
+    /// Returns every state a tile may be
+    pub fn states(&self) -> &BTreeSet<T> {
+        &self.states
+    }
+}
+
+impl<T: State> WfcRules<T> for SmoothingRules<T> {
+    type Cache = ();
+
+    fn get_states(&self, _map: WfcView<'_, T, Self>) -> BTreeSet<T> {
+        self.states.clone()
+    }
+
+    fn entropy_at(&self, map: WfcView<'_, T, Self>) -> f64 {
+        let (x, y) = *map.pos();
+        let states = match &map.wfc[map.wfc.xy_pair(x, y)] {
+            Tile::Definite(_) => return 0.0,
+            Tile::Indefinite(states) => states,
+        };
+
+        let neighbors = map.neighbors_8()
+            .into_iter()
+            .map(|idx| &map.wfc[idx])
+            .collect::<Vec<_>>();
+        let agreeing = neighbors.iter()
+            .filter(|tile| matches!(tile, Tile::Definite(state) if states.contains(state)))
+            .count();
+        let shared = Span::from_tiles(neighbors)
+            .union_states()
+            .intersection(states)
+            .count()
+            .max(1);
+        shared as f64 / (agreeing + 1) as f64
+    }
+
+    fn dependents(&self, _map: WfcView<'_, T, Self>) -> Vec<usize> {
+        Vec::new() // No tile's states depend on another's
+    }
+}
+
+/// A tile of a Wang tileset, with a code on each of its edges. Two tiles may only be placed
+/// next to each other where the edges they share have the same code
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct WangTile {
+    /// The code of each edge, ordered as in [Direction::ALL]
+    pub edges: [u8; 4],
+}
+
+impl State for WangTile {}
+
+impl WangTile {
+    /// Creates a tile with the north, east, south & west [edges]
+    pub fn new(edges: [u8; 4]) -> Self {
+        Self { edges }
+    }
+
+    /// Returns the code of the edge facing [dir]
+    pub fn edge(&self, dir: Direction) -> u8 {
+        self.edges[dir as usize]
+    }
+
+    /// Returns this tile turned a quarter turn clockwise, so its north edge faces east
+    pub fn rotated(&self) -> Self {
+        let [n, e, s, w] = self.edges;
+        Self::new([w, n, e, s])
+    }
+
+    /// Returns this tile in each of its 4 rotations, leaving out repeats for symmetric tiles
+    pub fn rotations(&self) -> BTreeSet<Self> {
+        let mut rotations = BTreeSet::new();
+        let mut tile = *self;
+        for _ in 0..4 {
+            rotations.insert(tile);
+            tile = tile.rotated();
+        }
+        rotations
+    }
+}
+
+/// Rules for placing [WangTile]s so every pair of neighbors shares an edge code
+#[derive(Debug, Clone)]
+pub struct WangRules {
+    tiles: BTreeSet<WangTile>,
+}
+
+impl WangRules {
+    /// Creates the rules for a map whose tiles may be any of [tiles]
+    pub fn new(tiles: BTreeSet<WangTile>) -> Self {
+        Self { tiles }
+    }
+
+    /// Creates the rules for a map whose tiles may be any rotation of [tiles]
+    pub fn with_rotations(tiles: impl IntoIterator<Item=WangTile>) -> Self {
+        Self::new(tiles.into_iter().flat_map(|tile| tile.rotations()).collect())
+    }
+
+    /// Returns every tile that may be placed
+    pub fn tiles(&self) -> &BTreeSet<WangTile> {
+        &self.tiles
+    }
+}
+
+impl WfcRules<WangTile> for WangRules {
+    type Cache = ();
+
+    fn get_states(&self, map: WfcView<'_, WangTile, Self>) -> BTreeSet<WangTile> {
+        let mut possible = self.tiles.clone();
+        for dir in Direction::ALL {
+            // The neighbor's edge facing back towards this tile must match one of them
+            let codes = match map.in_direction(dir) {
+                Some(Tile::Definite(tile)) => BTreeSet::from([tile.edge(dir.opposite())]),
+                Some(Tile::Indefinite(tiles)) => tiles.iter()
+                    .map(|tile| tile.edge(dir.opposite()))
+                    .collect(),
+                None => continue,
+            };
+            possible.retain(|tile| codes.contains(&tile.edge(dir)));
+        }
+        possible
+    }
+
+    fn entropy(&self, tile: &Tile<WangTile>) -> f64 {
+        match tile {
+            Tile::Definite(_) => 0.0,
+            Tile::Indefinite(tiles) => tiles.len() as f64,
+        }
+    }
+
+    fn dependents(&self, map: WfcView<'_, WangTile, Self>) -> Vec<usize> {
+        map.neighbors()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::wfc::{Direction, StepOutcome, Tile, Topology, Wfc, WfcError, WfcRules};
+
+    use super::{NoAdjacentEqualRules, SmoothingRules, WangRules, WangTile};
+
+    #[test]
+    fn no_adjacent_equal() {
+        let states = BTreeSet::from(['r', 'g', 'b']);
+        let tiles = vec![Tile::Indefinite(states.clone()); 9];
+        let mut wfc = Wfc::new(3, 3, tiles, NoAdjacentEqualRules::new(states));
+        wfc.collapse_at(0, 'r').unwrap();
+        for idx in [1, 3] {
+            assert_eq!(wfc[idx], Tile::Indefinite(BTreeSet::from(['g', 'b'])));
+            assert_eq!(wfc.collapse_at(idx, 'r'), Err(WfcError::Contradiction { idx, x: idx % 3, y: idx / 3 }));
+        }
+        assert_eq!(wfc[4], Tile::Indefinite(BTreeSet::from(['r', 'g', 'b'])));
+
+        wfc.run_with_backtracking().unwrap();
+        for idx in 0..9 {
+            for neighbor in wfc.view(idx).neighbors() {
+                assert_ne!(wfc[idx], wfc[neighbor]);
+            }
+        }
+    }
+
+    #[test]
+    fn no_adjacent_equal_hex() {
+        // (0, 1) is only next to (1, 0) on a hex map, through the diagonal of an even row
+        let states = BTreeSet::from(['r', 'g', 'b']);
+        for (topology, expected) in [(Topology::Square, states.clone()), (Topology::HexPointy, BTreeSet::from(['g', 'b']))] {
+            let tiles = vec![Tile::Indefinite(states.clone()); 4];
+            let mut wfc = Wfc::new(2, 2, tiles, NoAdjacentEqualRules::new(states.clone())).with_topology(topology);
+            wfc.collapse_at(1, 'r').unwrap();
+            assert_eq!(wfc[2], Tile::Indefinite(expected), "{:?}", topology);
+        }
+    }
+
+    #[test]
+    fn smoothing() {
+        let states = BTreeSet::from(['a', 'b']);
+        let tiles = vec![Tile::Indefinite(states.clone()); 25];
+        let mut wfc = Wfc::new(5, 5, tiles, SmoothingRules::new(states.clone()));
+        // Surround (1, 1) with 'a', leaving (4, 4) with only indefinite neighbors
+        for idx in [0, 1, 2, 5, 7, 10, 11, 12] {
+            wfc.collapse_at(idx, 'a').unwrap();
+        }
+
+        let surrounded = wfc.rules.entropy_at(wfc.view(6));
+        let isolated = wfc.rules.entropy_at(wfc.view(24));
+        assert_eq!(surrounded, 1.0 / 9.0);
+        assert_eq!(isolated, 2.0);
+        assert_eq!(wfc.entropy_ranking()[0].0, 6);
+
+        assert!(matches!(wfc.step_result(), Ok(StepOutcome::Collapsed { idx: 6, .. })));
+    }
+
+    #[test]
+    fn wang_rotations() {
+        let corner = WangTile::new([1, 1, 0, 0]);
+        assert_eq!(corner.rotated(), WangTile::new([0, 1, 1, 0]));
+        assert_eq!(corner.rotated().edge(Direction::East), corner.edge(Direction::North));
+        assert_eq!(corner.rotations().len(), 4);
+        assert_eq!(WangTile::new([1, 0, 1, 0]).rotations().len(), 2);
+        assert_eq!(WangTile::new([1; 4]).rotations().len(), 1);
+    }
+
+    #[test]
+    fn wang() {
+        // Every combination of two edge codes, so any partial map can be finished
+        let tiles = [[0; 4], [1, 0, 0, 0], [1, 1, 0, 0], [1, 0, 1, 0], [1, 1, 1, 0], [1; 4]];
+        let rules = WangRules::with_rotations(tiles.map(WangTile::new));
+        assert_eq!(rules.tiles().len(), 16);
+
+        let tiles = vec![Tile::Indefinite(rules.tiles().clone()); 16];
+        let mut wfc = Wfc::new(4, 4, tiles, rules);
+        wfc.collapse_at(5, WangTile::new([1, 0, 1, 0])).unwrap();
+        for (idx, dir) in [(1, Direction::South), (4, Direction::East), (6, Direction::West), (9, Direction::North)] {
+            let code = wfc[5].as_definite().edge(dir.opposite());
+            assert!(wfc[idx].as_indefinite().iter().all(|tile| tile.edge(dir) == code));
+        }
+
+        wfc.run().unwrap();
+        for idx in 0..16 {
+            let tile = wfc[idx].as_definite();
+            for dir in Direction::ALL {
+                if let Some(neighbor) = wfc.view(idx).definite_in(dir) {
+                    assert_eq!(tile.edge(dir), neighbor.edge(dir.opposite()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use core::fmt::Debug;
+
+#[cfg(feature = "std")]
+use rand::seq::IteratorRandom;
+#[cfg(feature = "std")]
+use rand::thread_rng;
+
+use super::{MaybeSync, State, Wfc, WfcRules};
+
+/// A heuristic for picking which tile the WFC algorithm collapses next
+pub trait SelectionStrategy<T: State, R: WfcRules<T>>: Debug + MaybeSync {
+    /// Returns the index of the indefinite tile to collapse, or None if there are none left
+    fn select(&mut self, wfc: &Wfc<T, R>) -> Option<usize>;
+}
+
+/// How the lowest-entropy selection picks between tiles tied for the lowest entropy, see
+/// [Wfc::with_tie_break]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum TieBreak {
+    /// Picks a random tied tile
+    #[default]
+    Random,
+    /// Picks the tied tile with the lowest index, so ties resolve left-to-right
+    FirstIndex,
+    /// Picks the tied tile with the highest index
+    LastIndex,
+}
+
+/// Collapses tiles in order, left-to-right then top-to-bottom
+#[derive(Debug, Clone, Default)]
+pub struct ScanlineSelection;
+
+impl<T: State, R: WfcRules<T>> SelectionStrategy<T, R> for ScanlineSelection {
+    fn select(&mut self, wfc: &Wfc<T, R>) -> Option<usize> {
+        wfc.indefinite_indices().next()
+    }
+}
+
+/// Collapses any indefinite tile, ignoring entropy
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct RandomSelection;
+
+#[cfg(feature = "std")]
+impl<T: State, R: WfcRules<T>> SelectionStrategy<T, R> for RandomSelection {
+    fn select(&mut self, wfc: &Wfc<T, R>) -> Option<usize> {
+        wfc.indefinite_indices().choose(&mut thread_rng())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::wfc::{RandomSelection, ScanlineSelection, TieBreak, Tile, Wfc, WfcRules, WfcView};
+
+    #[derive(Debug)]
+    struct S;
+
+    impl WfcRules<i32> for S {
+        type Cache = ();
+
+        fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0, 1])
+        }
+    }
+
+    fn wfc() -> Wfc<i32, S> {
+        Wfc::new(4, 4, vec![Tile::Indefinite(BTreeSet::from([0, 1])); 16], S)
+    }
+
+    #[test]
+    fn scanline() {
+        let mut wfc = wfc().with_selection(ScanlineSelection);
+        for step in 1..=16 {
+            assert_eq!(wfc.try_step(), Ok(true));
+            assert_eq!(wfc.definite_indices().collect::<Vec<_>>(), (0..step).collect::<Vec<_>>());
+        }
+        assert_eq!(wfc.try_step(), Ok(false));
+    }
+
+    #[test]
+    fn random() {
+        let mut wfc = wfc().with_selection(RandomSelection);
+        for step in 1..=16 {
+            assert_eq!(wfc.try_step(), Ok(true));
+            assert_eq!(wfc.definite_indices().count(), step);
+        }
+        assert_eq!(wfc.try_step(), Ok(false));
+    }
+
+    #[test]
+    fn tie_break() {
+        let mut first = wfc().with_tie_break(TieBreak::FirstIndex);
+        assert_eq!(first.tie_break(), TieBreak::FirstIndex);
+        for idx in 0..16 {
+            assert_eq!(first.step_diff().unwrap().unwrap().collapsed, idx);
+        }
+
+        let mut last = wfc().with_tie_break(TieBreak::LastIndex);
+        assert_eq!(last.step_diff().unwrap().unwrap().collapsed, 15);
+
+        let chosen = (0..16)
+            .map(|seed| {
+                let mut wfc = wfc();
+                assert_eq!(wfc.tie_break(), TieBreak::Random);
+                wfc.step_diff_with(&mut StdRng::seed_from_u64(seed)).unwrap().unwrap().collapsed
+            })
+            .collect::<BTreeSet<_>>();
+        assert!(chosen.len() > 1, "{:?}", chosen);
+    }
+}
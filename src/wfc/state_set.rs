@@ -0,0 +1,243 @@
+use alloc::collections::BTreeSet;
+use core::fmt::Debug;
+
+use super::State;
+
+/// A set of the states a tile may still become
+pub trait StateSet<T>: Clone + Debug + Eq {
+    /// Returns a set with no states
+    fn empty() -> Self;
+
+    /// Adds [state], returning whether it was not already present
+    fn insert(&mut self, state: T) -> bool;
+
+    /// Removes [state], returning whether it was present
+    fn remove(&mut self, state: &T) -> bool;
+
+    /// Returns whether [state] is in the set
+    fn contains(&self, state: &T) -> bool;
+
+    /// Returns the number of states in the set
+    fn len(&self) -> usize;
+
+    /// Returns whether the set has no states
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every state that is not also in [other]
+    fn intersect_with(&mut self, other: &Self);
+
+    /// Adds every state in [other]
+    fn union_with(&mut self, other: &Self);
+}
+
+impl<T: State> StateSet<T> for BTreeSet<T> {
+    fn empty() -> Self {
+        BTreeSet::new()
+    }
+
+    fn insert(&mut self, state: T) -> bool {
+        BTreeSet::insert(self, state)
+    }
+
+    fn remove(&mut self, state: &T) -> bool {
+        BTreeSet::remove(self, state)
+    }
+
+    fn contains(&self, state: &T) -> bool {
+        BTreeSet::contains(self, state)
+    }
+
+    fn len(&self) -> usize {
+        BTreeSet::len(self)
+    }
+
+    fn intersect_with(&mut self, other: &Self) {
+        self.retain(|state| other.contains(state));
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        self.extend(other.iter().cloned());
+    }
+}
+
+/// A state type with a fixed number of values, each with a dense index in 0..COUNT
+pub trait StateIndex: State {
+    /// The number of values of the state
+    const COUNT: usize;
+
+    /// Returns the index of this state, which is below [StateIndex::COUNT]
+    fn index(&self) -> usize;
+
+    /// Returns the state with index [i]
+    ///
+    /// # Panics
+    /// * If [i] >= [StateIndex::COUNT]
+    fn from_index(i: usize) -> Self;
+}
+
+/// A state type whose every value can be listed, so a map can start with all of them
+pub trait FullStates: State {
+    /// Returns every value of the state
+    fn all() -> BTreeSet<Self>;
+}
+
+/// A set of up to 64 states stored by their index as the bits of a [u64]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct BitSet(pub u64);
+
+impl BitSet {
+    /// The largest number of states a [BitSet] can hold
+    pub const CAPACITY: usize = u64::BITS as usize;
+
+    /// Creates a set of [states], where [index] gives the bit of each
+    ///
+    /// # Panics
+    /// * If [index] returns an index >= [BitSet::CAPACITY]
+    pub fn from_states<'a, T: 'a>(states: impl IntoIterator<Item=&'a T>, index: impl Fn(&T) -> usize) -> Self {
+        let mut set = Self::empty();
+        for state in states {
+            set.insert(index(state));
+        }
+        set
+    }
+
+    /// Returns the states in this set, where [from_index] turns a bit back into its state
+    pub fn to_states<T: State>(self, from_index: impl Fn(usize) -> T) -> BTreeSet<T> {
+        self.iter().map(from_index).collect()
+    }
+
+    /// Returns the indices in this set in ascending order
+    pub fn iter(self) -> impl Iterator<Item=usize> {
+        let bits = self.0;
+        (0..Self::CAPACITY).filter(move |i| bits & (1 << i) != 0)
+    }
+}
+
+impl StateSet<usize> for BitSet {
+    fn empty() -> Self {
+        BitSet(0)
+    }
+
+    /// # Panics
+    /// * If [state] >= [BitSet::CAPACITY]
+    fn insert(&mut self, state: usize) -> bool {
+        assert!(state < Self::CAPACITY, "BitSet can only hold indices below 64");
+        let present = self.contains(&state);
+        self.0 |= 1 << state;
+        !present
+    }
+
+    fn remove(&mut self, state: &usize) -> bool {
+        let present = self.contains(state);
+        if present {
+            self.0 &= !(1 << state);
+        }
+        present
+    }
+
+    fn contains(&self, state: &usize) -> bool {
+        *state < Self::CAPACITY && self.0 & (1 << state) != 0
+    }
+
+    fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    fn intersect_with(&mut self, other: &Self) {
+        self.0 &= other.0;
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        self.0 |= other.0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::wfc::{AdjacencyRules, Direction, State, Tile, Wfc, WfcRules};
+
+    use super::{BitSet, StateIndex, StateSet};
+
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+    struct Level(u8);
+
+    impl State for Level {}
+
+    impl StateIndex for Level {
+        const COUNT: usize = 4;
+
+        fn index(&self) -> usize {
+            self.0 as usize
+        }
+
+        fn from_index(i: usize) -> Self {
+            Level(i as u8)
+        }
+    }
+
+    /// Runs [tiles] to completion with [rules], seeding the rng with [seed]
+    fn run<R: WfcRules<Level>>(width: usize, tiles: Vec<Tile<Level>>, rules: R, seed: u64) -> Vec<Tile<Level>> {
+        let mut wfc = Wfc::new(width, tiles.len() / width, tiles, rules);
+        wfc.run_with_backtracking_with(&mut StdRng::seed_from_u64(seed)).unwrap();
+        wfc.into_partial()
+    }
+
+    #[test]
+    fn bit_set() {
+        let mut set = BitSet::empty();
+        assert!(set.insert(3));
+        assert!(!set.insert(3));
+        assert!(set.insert(63));
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&63) && !set.contains(&64));
+        assert!(set.remove(&3));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![63]);
+    }
+
+    #[test]
+    fn backends_agree() {
+        // A strip where the only way from 1 to 0 in four steps east is 1, 1, 2, 3, 0
+        let mut rules = AdjacencyRules::new();
+        for (a, b) in [(0, 1), (1, 2), (2, 3), (3, 0), (1, 1)] {
+            rules.allow(Level(a), Direction::East, Level(b));
+        }
+        for a in 0..4 {
+            for b in 0..4 {
+                rules.allow(Level(a), Direction::South, Level(b));
+            }
+        }
+        let all = rules.states().clone();
+        let mut strip = vec![Tile::Indefinite(all); 5];
+        strip[0] = Tile::Definite(Level(1));
+        strip[4] = Tile::Definite(Level(0));
+
+        let tree = run(5, strip.clone(), rules.clone(), 0);
+        assert_eq!(run(5, strip, rules.compile(), 0), tree);
+        assert_eq!(tree, [1, 1, 2, 3, 0].map(|state| Tile::Definite(Level(state))));
+
+        // A map with many solutions, where both backends must make the same choices
+        let mut rules = AdjacencyRules::new();
+        for a in 0..4u8 {
+            for b in a.saturating_sub(1)..(a + 2).min(4) {
+                rules.allow(Level(a), Direction::East, Level(b));
+                rules.allow(Level(a), Direction::South, Level(b));
+            }
+        }
+        let tiles = vec![Tile::Indefinite(rules.states().clone()); 64];
+        for seed in 0..4 {
+            assert_eq!(run(8, tiles.clone(), rules.compile(), seed), run(8, tiles.clone(), rules.clone(), seed));
+        }
+
+        let tree = BTreeSet::from([Level(0), Level(2)]);
+        let mut bits = BitSet::from_states(&tree, Level::index);
+        bits.intersect_with(&BitSet::from_states(&[Level(2), Level(3)], Level::index));
+        assert_eq!(bits.to_states(Level::from_index), BTreeSet::from([Level(2)]));
+    }
+}
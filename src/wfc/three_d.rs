@@ -0,0 +1,354 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::mem::replace;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+#[cfg(feature = "std")]
+use rand::thread_rng;
+
+use super::{State, Tile, WfcError};
+
+/// A controller for dictating rules of the 3D WFC algorithm
+pub trait WfcRules3<T: State>: Sized {
+    /// Returns the valid states that are possible in [map.pos()]
+    fn get_states(&self, map: WfcView3<'_, T, Self>) -> BTreeSet<T>;
+
+    /// Returns the entropy of [tile], the indefinite tile with the lowest is collapsed first
+    fn entropy(&self, _tile: &Tile<T>) -> f64 {
+        0.0
+    }
+}
+
+/// The main structure for the 3D WFC algorithm
+///
+/// Tiles are stored layer by layer, so the tile at (x, y, z) lives at `z*w*h + y*w + x`
+#[derive(Debug)]
+pub struct Wfc3<T: State, R: WfcRules3<T>> {
+    width: usize,
+    height: usize,
+    depth: usize,
+    rules: R,
+    map: Vec<Tile<T>>,
+}
+
+impl<T: State, R: WfcRules3<T>> Wfc3<T, R> {
+    /// Creates a new 3D WFC
+    pub fn new(width: usize, height: usize, depth: usize, tiles: Vec<Tile<T>>, rules: R) -> Self {
+        assert!(width > 0);
+        assert!(height > 0);
+        assert!(depth > 0);
+        assert_eq!(tiles.len(), width * height * depth, "Tiles.len() must be w*h*d");
+
+        Self {
+            map: tiles,
+            width,
+            height,
+            depth,
+            rules,
+        }
+    }
+
+    /// Returns the width of the map
+    #[inline(always)]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the map
+    #[inline(always)]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the depth of the map
+    #[inline(always)]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns a new view centered at [idx]
+    pub fn view(&self, idx: usize) -> WfcView3<'_, T, R> {
+        assert!(idx < self.map.len(), "idx must be inside wfc map");
+        let layer = self.width * self.height;
+        WfcView3 {
+            pos: (idx % self.width, (idx % layer) / self.width, idx / layer),
+            wfc: self,
+        }
+    }
+
+    /// Converts an (x, y, z) triple into an index into the map
+    pub fn xyz_index(&self, x: usize, y: usize, z: usize) -> usize {
+        z * self.width * self.height + y * self.width + x
+    }
+
+    /// Collapses the lowest entropy tile and re-derives the rest
+    ///
+    /// Returns None once every tile is definite or on any error, see [try_step] for which
+    #[cfg(feature = "std")]
+    pub fn step(&mut self) -> Option<()> {
+        self.step_with(&mut thread_rng())
+    }
+
+    /// Like [step], but makes every random choice with [rng]
+    pub fn step_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Option<()> {
+        self.try_step_with(rng).ok()?.then_some(())
+    }
+
+    /// Collapses the lowest entropy tile and re-derives the rest
+    ///
+    /// Returns Ok(false) once every tile is definite. If the collapse leaves another tile with
+    /// no states, the chosen state is removed from the collapsed tile and it is made indefinite
+    /// again, so the next step does not repeat it. Returns [WfcError::Contradiction3], leaving
+    /// the map unchanged, if the collapsed tile had no other state to try or had none at all
+    #[cfg(feature = "std")]
+    pub fn try_step(&mut self) -> Result<bool, WfcError> {
+        self.try_step_with(&mut thread_rng())
+    }
+
+    /// Like [try_step], but makes every random choice with [rng]
+    pub fn try_step_with<G: Rng + ?Sized>(&mut self, rng: &mut G) -> Result<bool, WfcError> {
+        let mut entropy_map = Vec::new();
+        for (idx, tile) in self.map.iter().enumerate() {
+            if let Tile::Indefinite(_) = tile {
+                let entropy = self.rules.entropy(tile);
+                if entropy.is_nan() {
+                    return Err(WfcError::InvalidEntropy(idx));
+                }
+                entropy_map.push((idx, entropy));
+            }
+        }
+        entropy_map.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        if entropy_map.is_empty() {
+            return Ok(false); // This means every state is definite
+        }
+
+        let lowest = entropy_map[0].1;
+        let tied = entropy_map.iter()
+            .position(|(_, e)| e.ne(&lowest))
+            .unwrap_or(entropy_map.len());
+        let selected = entropy_map[0..tied].choose(rng)
+            .expect("there is at least one indefinite tile")
+            .0;
+
+        let (state, old) = {
+            let states = self.map[selected].as_indefinite();
+            if states.is_empty() {
+                return Err(self.contradiction(selected));
+            }
+            let idx = rng.gen_range(0..states.len());
+            let state = states.iter().nth(idx).unwrap().clone();
+
+            let mut states = replace(&mut self.map[selected], Tile::Definite(state.clone()))
+                .into_indefinite();
+            states.remove(&state);
+            (state, states)
+        };
+
+        let mut states = Vec::with_capacity(entropy_map.len() - 1);
+        for (idx, _) in entropy_map {
+            if idx == selected {
+                continue; // This is the collapsed state;
+            }
+            // The rules may allow states the tile has already lost, so only keep the stored ones
+            let mut collapsed = self.rules.get_states(self.view(idx));
+            collapsed.retain(|state| self.map[idx].as_indefinite().contains(state));
+            if collapsed.is_empty() {
+                if old.is_empty() {
+                    // No alternatives for the selected tile, so leave the map as it was
+                    self.map[selected] = Tile::Indefinite(BTreeSet::from([state]));
+                    return Err(self.contradiction(selected));
+                }
+                // Since we removed the randomly chosen state from the old set,
+                // The next iteration will not make the same mistake
+                self.map[selected] = Tile::Indefinite(old);
+                return Ok(true);
+            }
+            states.push((idx, collapsed));
+        }
+
+        for (idx, states) in states {
+            let tile = match states.len() {
+                1 => Tile::Definite(states.into_iter().next().unwrap()),
+                _ => Tile::Indefinite(states),
+            };
+            self.map[idx] = tile;
+        }
+
+        Ok(true)
+    }
+
+    /// Returns a [WfcError::Contradiction3] at the tile at [idx]
+    fn contradiction(&self, idx: usize) -> WfcError {
+        let (x, y, z) = self.view(idx).pos;
+        WfcError::Contradiction3 { idx, x, y, z }
+    }
+}
+
+/// A view of the 3D WFC map
+#[derive(Debug, Clone)]
+pub struct WfcView3<'wfc, T: State, R: WfcRules3<T>> where Self: 'wfc {
+    wfc: &'wfc Wfc3<T, R>,
+    pos: (usize, usize, usize),
+}
+
+impl<'wfc, T: State, R: WfcRules3<T>> WfcView3<'wfc, T, R> where Self: 'wfc {
+    /// Returns the position associated with this view
+    #[inline(always)]
+    pub fn pos(&self) -> &(usize, usize, usize) {
+        &self.pos
+    }
+
+    /// Returns the tile at the xyz triple: [x], [y], [z]
+    pub fn get_at(&self, x: usize, y: usize, z: usize) -> &'wfc Tile<T> {
+        &self.wfc.map[self.wfc.xyz_index(x, y, z)]
+    }
+
+    /// Returns the tile at self.pos()
+    pub fn get(&self) -> &'wfc Tile<T> {
+        let (x, y, z) = self.pos;
+        self.get_at(x, y, z)
+    }
+
+    /// Returns the in-bounds face neighbors of self.pos()
+    ///
+    /// Neighbors are ordered -x, +x, -y, +y, -z, +z, skipping any outside the map
+    pub fn neighbors_6(&self) -> Vec<&'wfc Tile<T>> {
+        let (x, y, z) = self.pos;
+        let (w, h, d) = (self.wfc.width, self.wfc.height, self.wfc.depth);
+        [
+            (x > 0).then(|| (x - 1, y, z)),
+            (x + 1 < w).then(|| (x + 1, y, z)),
+            (y > 0).then(|| (x, y - 1, z)),
+            (y + 1 < h).then(|| (x, y + 1, z)),
+            (z > 0).then(|| (x, y, z - 1)),
+            (z + 1 < d).then(|| (x, y, z + 1)),
+        ]
+            .into_iter()
+            .flatten()
+            .map(|(x, y, z)| self.get_at(x, y, z))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::wfc::{Tile, WfcError};
+
+    use super::{Wfc3, WfcRules3, WfcView3};
+
+    #[derive(Debug)]
+    struct S;
+
+    impl WfcRules3<i32> for S {
+        fn get_states(&self, _: WfcView3<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0])
+        }
+    }
+
+    #[test]
+    fn index_scheme() {
+        let wfc = Wfc3::new(2, 3, 4, (0..24).map(Tile::Definite).collect(), S);
+        assert_eq!(wfc.xyz_index(1, 2, 3), 3 * 6 + 2 * 2 + 1);
+        assert_eq!(wfc.view(23).pos(), &(1, 2, 3));
+        assert_eq!(wfc.view(23).get(), &Tile::Definite(23));
+    }
+
+    #[test]
+    fn neighbors_6() {
+        let wfc = Wfc3::new(3, 3, 3, (0..27).map(Tile::Definite).collect(), S);
+
+        let corner = wfc.view(0).neighbors_6();
+        assert_eq!(corner, vec![&Tile::Definite(1), &Tile::Definite(3), &Tile::Definite(9)]);
+
+        let center = wfc.view(13).neighbors_6();
+        assert_eq!(center, [12, 14, 10, 16, 4, 22].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn collapse_cube() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0])); 8];
+        let mut wfc = Wfc3::new(2, 2, 2, tiles, S);
+        while wfc.step().is_some() {}
+        assert!(wfc.map.iter().all(|tile| tile == &Tile::Definite(0)));
+        assert_eq!(wfc.try_step(), Ok(false));
+    }
+
+    /// Rules that allow every state and give every tile the entropy they hold
+    #[derive(Debug)]
+    struct Any(f64);
+
+    impl WfcRules3<i32> for Any {
+        fn get_states(&self, _: WfcView3<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::from([0, 1, 2])
+        }
+
+        fn entropy(&self, _tile: &Tile<i32>) -> f64 {
+            self.0
+        }
+    }
+
+    /// Rules that allow nothing anywhere
+    #[derive(Debug)]
+    struct Nothing;
+
+    impl WfcRules3<i32> for Nothing {
+        fn get_states(&self, _: WfcView3<'_, i32, Self>) -> BTreeSet<i32> {
+            BTreeSet::new()
+        }
+    }
+
+    #[test]
+    fn nan_entropy() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 8];
+        let mut wfc = Wfc3::new(2, 2, 2, tiles, Any(f64::NAN));
+        assert_eq!(wfc.try_step(), Err(WfcError::InvalidEntropy(0)));
+        assert_eq!(wfc.step(), None);
+    }
+
+    #[test]
+    fn empty_tile() {
+        let mut tiles = vec![Tile::Definite(0); 8];
+        tiles[7] = Tile::Indefinite(BTreeSet::new());
+        let mut wfc = Wfc3::new(2, 2, 2, tiles, Any(0.0));
+        assert_eq!(wfc.try_step(), Err(WfcError::Contradiction3 { idx: 7, x: 1, y: 1, z: 1 }));
+    }
+
+    #[test]
+    fn removed_states_stay_removed() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 8];
+        let mut wfc = Wfc3::new(2, 2, 2, tiles, Any(0.0));
+        let mut rng = StdRng::seed_from_u64(0);
+        while wfc.try_step_with(&mut rng).unwrap() {
+            for tile in &wfc.map {
+                match tile {
+                    Tile::Definite(state) => assert!([0, 1].contains(state)),
+                    Tile::Indefinite(states) => assert_eq!(states, &BTreeSet::from([0, 1])),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn contradiction() {
+        // The collapsed tile has another state to try, so it is put back without the first
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1])); 2];
+        let mut wfc = Wfc3::new(2, 1, 1, tiles, Nothing);
+        assert_eq!(wfc.try_step(), Ok(true));
+        assert_eq!(wfc.map.iter().filter(|tile| tile.as_indefinite().len() == 1).count(), 1);
+
+        // With nothing left to try, the step fails rather than looking finished
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0])); 2];
+        let mut wfc = Wfc3::new(2, 1, 1, tiles.clone(), Nothing);
+        assert!(matches!(wfc.try_step(), Err(WfcError::Contradiction3 { .. })));
+        assert_eq!(wfc.map, tiles);
+    }
+}
@@ -1,10 +1,135 @@
-use std::fmt::Debug;
-use std::ops::Range;
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::ops::{BitOr, Range};
 
 use crate::wfc::{Tile, Wfc, WfcRules};
 
 use super::State;
 
+/// The four orthogonal directions on the map, with north pointing towards y = 0
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    /// Every direction, in clockwise order starting from north
+    pub const ALL: [Direction; 4] = [Self::North, Self::East, Self::South, Self::West];
+
+    /// Returns the direction pointing the other way
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::North => Self::South,
+            Self::East => Self::West,
+            Self::South => Self::North,
+            Self::West => Self::East,
+        }
+    }
+
+    /// Returns the direction a quarter turn clockwise from this one
+    pub fn clockwise(self) -> Self {
+        match self {
+            Self::North => Self::East,
+            Self::East => Self::South,
+            Self::South => Self::West,
+            Self::West => Self::North,
+        }
+    }
+
+    /// Returns this direction mirrored left-to-right, swapping east and west
+    pub fn mirrored(self) -> Self {
+        match self {
+            Self::East => Self::West,
+            Self::West => Self::East,
+            dir => dir,
+        }
+    }
+
+    /// Returns the (dx, dy) step taken when moving in this direction
+    pub fn offset(self) -> (isize, isize) {
+        match self {
+            Self::North => (0, -1),
+            Self::East => (1, 0),
+            Self::South => (0, 1),
+            Self::West => (-1, 0),
+        }
+    }
+}
+
+/// A set of the map's edges, one flag per [Direction], see [WfcView::on_edge]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct EdgeFlags(u8);
+
+impl EdgeFlags {
+    /// No edges
+    pub const NONE: Self = Self(0);
+
+    /// Returns whether the edge in [dir] is in this set
+    pub fn contains(self, dir: Direction) -> bool {
+        self.0 & Self::from(dir).0 != 0
+    }
+
+    /// Adds the edge in [dir] to this set
+    pub fn insert(&mut self, dir: Direction) {
+        self.0 |= Self::from(dir).0;
+    }
+
+    /// Returns how many edges are in this set
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Returns whether this set has no edges
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<Direction> for EdgeFlags {
+    fn from(dir: Direction) -> Self {
+        Self(1 << dir as u8)
+    }
+}
+
+impl BitOr for EdgeFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// How the tiles of the map are laid out, which decides what counts as a neighbor
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum Topology {
+    /// Square tiles with four orthogonal neighbors
+    #[default]
+    Square,
+    /// Pointy-topped hexagons in rows, with every odd row shifted half a tile east
+    HexPointy,
+    /// Flat-topped hexagons in columns, with every odd column shifted half a tile south
+    HexFlat,
+}
+
+impl Topology {
+    /// Returns the (dx, dy) steps to every neighbor of the tile at [x], [y], clockwise from north
+    /// or north-east
+    pub fn offsets(self, x: usize, y: usize) -> &'static [(isize, isize)] {
+        match self {
+            Self::Square => &[(0, -1), (1, 0), (0, 1), (-1, 0)],
+            Self::HexPointy if y.is_multiple_of(2) => &[(0, -1), (1, 0), (0, 1), (-1, 1), (-1, 0), (-1, -1)],
+            Self::HexPointy => &[(1, -1), (1, 0), (1, 1), (0, 1), (-1, 0), (0, -1)],
+            Self::HexFlat if x.is_multiple_of(2) => &[(0, -1), (1, -1), (1, 0), (0, 1), (-1, 0), (-1, -1)],
+            Self::HexFlat => &[(0, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0)],
+        }
+    }
+}
+
 /// A view of the WFC map
 ///
 /// Comes with utility methods to inspect parts of the map in order to determine
@@ -34,51 +159,80 @@ impl<'wfc, T: State, R: WfcRules<T>> WfcView<'wfc, T, R> where Self: 'wfc {
         &self.pos
     }
 
-    /// Returns a span of the elements in the row at [row]
+    /// Returns the rules of the map, so helpers given only a view can read their configuration
+    #[inline(always)]
+    pub fn rules(&self) -> &'wfc R {
+        &self.wfc.rules
+    }
+
+    /// Returns self.pos() scaled so the top-left of the map is (0, 0) and the bottom-right is
+    /// (1, 1)
+    ///
+    /// A map only one tile wide or tall has a normalized x or y of 0
+    pub fn normalized_pos(&self) -> (f64, f64) {
+        fn normalize(v: usize, len: usize) -> f64 {
+            match len {
+                1 => 0.0,
+                _ => v as f64 / (len - 1) as f64,
+            }
+        }
+
+        let (x, y) = self.pos;
+        (normalize(x, self.width()), normalize(y, self.height()))
+    }
+
+    /// Converts an xy-pair into an index into the map, see [Wfc::xy_pair]
+    #[inline(always)]
+    pub fn index_of(&self, x: usize, y: usize) -> usize {
+        self.wfc.xy_pair(x, y)
+    }
+
+    /// Returns the index into the map of self.pos()
+    #[inline(always)]
+    pub fn self_index(&self) -> usize {
+        let (x, y) = self.pos;
+        self.index_of(x, y)
+    }
+
+    /// Returns a span of the elements in the row at [row], see [Wfc::row]
     ///
     /// # Panics
     /// * If row >= self.height()
     pub fn row_at(&self, row: usize) -> Span<'wfc, T> {
-        let height = self.height();
-        assert!(row < height, "row must be inside of the map's height");
-        let idx = row * self.width();
-        Span(vec![&self.wfc.map[idx..idx + height]])
+        self.wfc.row(row)
     }
 
-    /// Returns a span of the the elements in the column at [col]
+    /// Returns a span of the the elements in the column at [col], see [Wfc::col]
     ///
     /// # Panics
     /// * If col >= self.width()
     pub fn col_at(&self, col: usize) -> Span<'wfc, T> {
-        let width = self.width();
-        assert!(col < width, "column must be inside of the map's width");
-        Span(self.wfc.map.as_slice()
-            .chunks(width)
-            .map(|chunk| &chunk[col..col + 1])
-            .collect())
+        self.wfc.col(col)
     }
 
-    /// Returns a span of the elements in the rectangle formed by the area of [x] and [y]
+    /// Returns a span of the elements in the rectangle formed by the area of [x] and [y], see
+    /// [Wfc::span]
     ///
     /// # Panics
     /// * If [x].len() == 0
-    /// * If [x].len() == 0
-    /// * If [x].end >= self.width()
-    /// * If [y].end >= self.height()
+    /// * If [y].len() == 0
+    /// * If [x].end > self.width()
+    /// * If [y].end > self.height()
     pub fn span(&self, x: Range<usize>, y: Range<usize>) -> Span<'wfc, T> {
-        assert_ne!(x.len(), 0, "x-range cannot be zero-width");
-        assert_ne!(y.len(), 0, "y-range cannot be zero-height");
-        let width = self.width();
-
-        assert!(x.end < width, "x-range must be inside of the map's width");
-        assert!(y.end < self.height(), "y-range must be inside of the map's height");
+        self.wfc.span(x, y)
+    }
 
-        Span(self.wfc.map.as_slice()
-            .chunks(width)
-            .take(y.end)
-            .skip(y.start)
-            .map(move |chunk| &chunk[x.clone()])
-            .collect())
+    /// Returns the span of [x]-[y] with both ranges cut off at the edges of the map
+    ///
+    /// Unlike [span] this never panics, and ranges that miss the map give an empty span
+    pub fn span_clamped(&self, x: Range<usize>, y: Range<usize>) -> Span<'wfc, T> {
+        let (width, height) = (self.width(), self.height());
+        let x = x.start.min(width)..x.end.min(width);
+        let y = y.start.min(height)..y.end.min(height);
+        if x.is_empty() || y.is_empty() {
+            return Span(Vec::new());
+        }
+        self.span(x, y)
     }
 
     /// Returns the span in [x] from the row at [row]
@@ -86,17 +240,17 @@ impl<'wfc, T: State, R: WfcRules<T>> WfcView<'wfc, T, R> where Self: 'wfc {
     /// # Panics
     /// * If [row] >= self.height()
     /// * If [x].len() == 0
-    /// * If [x].end >= self.width()
+    /// * If [x].end > self.width()
     pub fn row_span_at(&self, row: usize, x: Range<usize>) -> Span<'wfc, T> {
         let width = self.width();
         assert!(row < self.height(), "row must be inside of the map's height");
         assert_ne!(x.len(), 0, "x-range cannot be zero-width");
-        assert!(x.end < width, "x-range must be inside of the map's width");
+        assert!(x.end <= width, "x-range must be inside of the map's width");
 
         let y = row * width;
         let y0 = y + x.start;
         let y1 = y + x.end;
-        Span(vec![&self.wfc.map.as_slice()[y0..y1]])
+        Span::from_slices(vec![&self.wfc.map.as_slice()[y0..y1]])
     }
 
     /// Returns the span in [y] from the column at [col]
@@ -104,14 +258,14 @@ impl<'wfc, T: State, R: WfcRules<T>> WfcView<'wfc, T, R> where Self: 'wfc {
     /// # Panics
     /// * If [col] >= self.width()
     /// * If [y].len() == 0
-    /// * If [y].end >= self.height()
+    /// * If [y].end > self.height()
     pub fn col_span_at(&self, col: usize, y: Range<usize>) -> Span<'wfc, T> {
         let width = self.width();
         assert!(col < self.width(), "col must be inside of the map's width");
         assert_ne!(y.len(), 0, "y-range cannot be zero-height");
-        assert!(y.end < self.height(), "y-range must be inside of the map's width");
+        assert!(y.end <= self.height(), "y-range must be inside of the map's height");
 
-        Span(self.wfc.map
+        Span::from_slices(self.wfc.map
             .chunks(width)
             .take(y.end)
             .skip(y.start)
@@ -129,42 +283,212 @@ impl<'wfc, T: State, R: WfcRules<T>> WfcView<'wfc, T, R> where Self: 'wfc {
         self.row_at(self.pos.1)
     }
 
-    /// Returns the tile at the xy pair: [col], [row]
-    pub fn get_at(&self, row: usize, col: usize) -> &'wfc Tile<T> {
-        &self.wfc.map[row * self.width() + col]
+    /// Returns the span of the diagonal running from the top-left to the bottom-right through
+    /// self.pos(), one tile per row
+    pub fn diagonal_down(&self) -> Span<'wfc, T> {
+        let (x, y) = self.pos;
+        let back = x.min(y);
+        let (sx, sy) = (x - back, y - back);
+        let len = (self.width() - sx).min(self.height() - sy);
+        self.diagonal((0..len).map(|i| (sx + i, sy + i)))
+    }
+
+    /// Returns the span of the diagonal running from the bottom-left to the top-right through
+    /// self.pos(), one tile per row starting at the bottom-left
+    pub fn diagonal_up(&self) -> Span<'wfc, T> {
+        let (x, y) = self.pos;
+        let back = x.min(self.height() - 1 - y);
+        let (sx, sy) = (x - back, y + back);
+        let len = (self.width() - sx).min(sy + 1);
+        self.diagonal((0..len).map(|i| (sx + i, sy - i)))
+    }
+
+    fn diagonal<I: Iterator<Item=(usize, usize)>>(&self, xy: I) -> Span<'wfc, T> {
+        Span::from_slices(xy
+            .map(|(x, y)| {
+                let idx = self.wfc.xy_pair(x, y);
+                &self.wfc.map[idx..idx + 1]
+            })
+            .collect())
+    }
+
+    /// Returns every tile within a Manhattan distance of [radius] of self.pos() with its x & y,
+    /// left-to-right then top-to-bottom
+    ///
+    /// Only tiles inside the map are included, even if it wraps
+    pub fn diamond(&self, radius: usize) -> Vec<(usize, usize, &'wfc Tile<T>)> {
+        let (x, y) = self.pos;
+        let (width, height) = (self.width(), self.height());
+        let mut tiles = Vec::new();
+        for ty in y.saturating_sub(radius)..=(y + radius).min(height - 1) {
+            let reach = radius - ty.abs_diff(y);
+            for tx in x.saturating_sub(reach)..=(x + reach).min(width - 1) {
+                tiles.push((tx, ty, &self.wfc.map[ty * width + tx]));
+            }
+        }
+        tiles
+    }
+
+    /// Returns the tiles in the row of self.pos() with their column, nearest to self.pos() first
+    ///
+    /// Tiles the same distance away are given right then left
+    pub fn row_outward(&self) -> impl Iterator<Item=(usize, &'wfc Tile<T>)> + 'wfc {
+        let (x, y) = self.pos;
+        let width = self.width();
+        let wfc = self.wfc;
+        (0..width)
+            .flat_map(move |dist| [Some(x + dist), x.checked_sub(dist).filter(|_| dist > 0)])
+            .flatten()
+            .filter(move |col| *col < width)
+            .map(move |col| (col, &wfc.map[y * width + col]))
+    }
+
+    /// Returns the tile at [x], [y]
+    pub fn get_at(&self, x: usize, y: usize) -> &'wfc Tile<T> {
+        &self.wfc.map[self.wfc.xy_pair(x, y)]
     }
 
     #[inline(always)]
     /// Returns the tile at self.pos()
     pub fn get(&self) -> &'wfc Tile<T> {
-        let (row, col) = self.pos;
-        self.get_at(row, col)
+        let (x, y) = self.pos;
+        self.get_at(x, y)
+    }
+
+    /// Returns the state of the tile next to self.pos() in [dir] if it is definite, or None if
+    /// it is indefinite or past the edge of the map
+    pub fn definite_in(&self, dir: Direction) -> Option<&'wfc T> {
+        self.in_direction(dir)
+            .filter(|tile| tile.is_definite())
+            .map(Tile::as_definite)
+    }
+
+    /// Returns the tile next to self.pos() in [dir], or None at the edge of the map
+    pub fn in_direction(&self, dir: Direction) -> Option<&'wfc Tile<T>> {
+        self.index_in_direction(dir).map(|idx| &self.wfc.map[idx])
+    }
+
+    /// Returns the edges of the map that self.pos() lies on, in the direction they face
+    ///
+    /// A map that wraps has no edges, so its tiles are never on one
+    pub fn on_edge(&self) -> EdgeFlags {
+        let mut edges = EdgeFlags::NONE;
+        if self.wfc.is_wrapping() {
+            return edges;
+        }
+        let (x, y) = self.pos;
+        for (dir, on_edge) in [
+            (Direction::North, y == 0),
+            (Direction::East, x + 1 == self.width()),
+            (Direction::South, y + 1 == self.height()),
+            (Direction::West, x == 0),
+        ] {
+            if on_edge {
+                edges.insert(dir);
+            }
+        }
+        edges
+    }
+
+    /// Returns whether self.pos() lies on both a horizontal and a vertical edge of the map
+    pub fn is_corner(&self) -> bool {
+        let edges = self.on_edge();
+        (edges.contains(Direction::North) || edges.contains(Direction::South))
+            && (edges.contains(Direction::East) || edges.contains(Direction::West))
     }
 
+    /// Returns whether self.pos() lies on any edge of the map
+    pub fn is_border(&self) -> bool {
+        !self.on_edge().is_empty()
+    }
+
+    /// Returns the indices of the in-bounds tiles next to self.pos() in the map's [Topology],
+    /// ordered as in [Topology::offsets]
+    ///
+    /// On the default square topology these are the same as [neighbors_4]
+    pub fn neighbors(&self) -> Vec<usize> {
+        self.wfc.neighbors_of(self.self_index())
+    }
+
+    /// Returns the indices of the in-bounds tiles orthogonally next to self.pos(), ordered as in
+    /// [Direction::ALL], whatever the map's [Topology]
+    pub fn neighbors_4(&self) -> Vec<usize> {
+        Direction::ALL.into_iter()
+            .filter_map(|dir| self.index_in_direction(dir))
+            .collect()
+    }
+
+    /// Returns the indices of the in-bounds tiles orthogonally or diagonally next to self.pos(),
+    /// left-to-right then top-to-bottom
+    pub fn neighbors_8(&self) -> Vec<usize> {
+        let (x, y) = self.pos;
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+            .filter(|offset| *offset != (0, 0))
+            .filter_map(|offset| self.wfc.offset_index(x, y, offset))
+            .collect()
+    }
+
+    /// Returns the index of the tile next to self.pos() in [dir], or None at the edge of a map that
+    /// does not wrap
+    pub fn index_in_direction(&self, dir: Direction) -> Option<usize> {
+        let (x, y) = self.pos;
+        self.wfc.offset_index(x, y, dir.offset())
+    }
+
+    /// Returns the span of the [width]x[height] section containing [x], [y] when the map is
+    /// divided into a grid of such sections, e.g. a sudoku block
+    ///
+    /// # Panics
+    /// * If [width] or [height] is zero or larger than the map
+    /// * If [x] >= self.width() or [y] >= self.height()
     pub fn section_at(&self, width: usize, height: usize, x: usize, y: usize) -> Span<'wfc, T> {
         let w = self.width();
         let h = self.height();
-        assert!(width < w, "width must be less than the width of the map");
-        assert!(height < w, "width must be less than the height of the map");
-        assert!(x < w, "x must be less than the height of the map");
-        assert!(y < w, "y must be less than the height of the map");
-
-        let sx = x / width;
-        let sy = y / height;
-        let rx = sx..(sx + width).max(w);
-        let ry = sy..(sy + height).max(h);
+        assert!(width > 0 && width <= w, "width must be inside of the map's width");
+        assert!(height > 0 && height <= h, "height must be inside of the map's height");
+        assert!(x < w, "x must be less than the width of the map");
+        assert!(y < h, "y must be less than the height of the map");
+
+        let sx = x / width * width;
+        let sy = y / height * height;
+        let rx = sx..(sx + width).min(w);
+        let ry = sy..(sy + height).min(h);
         self.span(rx, ry)
     }
 }
 
 #[derive(Debug)]
-pub struct Span<'wfc, T: State>(Vec<&'wfc [Tile<T>]>) where Self: 'wfc;
+pub struct Span<'wfc, T: State>(Vec<Vec<&'wfc Tile<T>>>) where Self: 'wfc;
 
 impl<'wfc, T: State> Span<'wfc, T> where Self: 'wfc {
-    /// Returns the length of each row in this span
+    /// Creates a span with one row per slice
+    pub(super) fn from_slices(rows: Vec<&'wfc [Tile<T>]>) -> Self {
+        Span(rows.into_iter()
+            .map(|row| row.iter().collect())
+            .collect())
+    }
+
+    /// Creates a span with a single row of [tiles]
+    pub(super) fn from_tiles(tiles: Vec<&'wfc Tile<T>>) -> Self {
+        Span(vec![tiles])
+    }
+
+    /// Returns this span with the rows of [other] added below its own, e.g. to treat two separate
+    /// areas as one constraint
+    ///
+    /// The rows may then differ in length. Column methods skip the rows that are too short
+    pub fn concat(mut self, other: Span<'wfc, T>) -> Span<'wfc, T> {
+        self.0.extend(other.0);
+        self
+    }
+
+    /// Returns the length of the longest row in this span, which is the length of every row
+    /// unless spans of different widths were [concat]enated
     pub fn width(&self) -> usize {
-        self.0.get(0)
-            .map(|slice| slice.len())
+        self.0.iter()
+            .map(|row| row.len())
+            .max()
             .unwrap_or(0)
     }
 
@@ -173,6 +497,26 @@ impl<'wfc, T: State> Span<'wfc, T> where Self: 'wfc {
         self.0.len()
     }
 
+    /// Returns the tile at [x], [y] within this span, or None if it is out of range
+    pub fn get(&self, x: usize, y: usize) -> Option<&'wfc Tile<T>> {
+        self.0.get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+    }
+
+    /// Returns the tile at [x], [y] within this span
+    ///
+    /// # Panics
+    /// * If there is no tile at [x], [y], such as when [x] is past the end of the row at [y]
+    pub fn at(&self, x: usize, y: usize) -> &'wfc Tile<T> {
+        self.get(x, y).expect("x & y must be inside the span")
+    }
+
+    /// Collects [f] of every tile in this span, in row-iterator order
+    pub fn collect_map<B: FromIterator<U>, U, F: Fn(&'wfc Tile<T>) -> U>(&self, f: F) -> B {
+        self.row_iter().map(f).collect()
+    }
+
     /// Returns a row-iterator for this span
     pub fn row_iter<'a>(&'a self) -> RowIter<'a, 'wfc, T> {
         RowIter {
@@ -190,38 +534,112 @@ impl<'wfc, T: State> Span<'wfc, T> where Self: 'wfc {
             y_idx: 0,
         }
     }
+
+    /// Returns an iterator of iterators, one for each row of this span
+    pub fn rows<'a>(&'a self) -> impl Iterator<Item=impl Iterator<Item=&'wfc Tile<T>> + 'a> + 'a {
+        self.0.iter().map(|row| row.iter().copied())
+    }
+
+    /// Returns an iterator of iterators, one for each column of this span
+    ///
+    /// Rows too short to reach a column are skipped in it
+    pub fn cols<'a>(&'a self) -> impl Iterator<Item=impl Iterator<Item=&'wfc Tile<T>> + 'a> + 'a {
+        (0..self.width()).map(move |x| self.0.iter().filter_map(move |row| row.get(x).copied()))
+    }
+
+    /// Returns a new span whose rows are the columns of this one
+    ///
+    /// Rows too short to reach a column are skipped in it, so the new rows may differ in length
+    pub fn transpose(&self) -> Span<'wfc, T> {
+        Span(self.cols().map(Iterator::collect).collect())
+    }
+
+    /// Returns the number of definite tiles in this span
+    pub fn count_definite(&self) -> usize {
+        self.row_iter()
+            .filter(|tile| matches!(tile, Tile::Definite(_)))
+            .count()
+    }
+
+    /// Returns a row-iterator over the values of the definite tiles in this span
+    pub fn definite_iter<'a>(&'a self) -> impl Iterator<Item=&'wfc T> + 'a {
+        self.row_iter().filter_map(|tile| match tile {
+            Tile::Definite(s) => Some(s),
+            Tile::Indefinite(_) => None,
+        })
+    }
+
+    /// Returns a row-iterator over the values of the definite tiles in this span
+    #[deprecated(note = "renamed to `definite_iter`")]
+    pub fn collapsed_values<'a>(&'a self) -> impl Iterator<Item=&'wfc T> + 'a {
+        self.definite_iter()
+    }
+
+    /// Returns a row-iterator over the states of the indefinite tiles in this span
+    pub fn indefinite_iter<'a>(&'a self) -> impl Iterator<Item=&'wfc BTreeSet<T>> + 'a {
+        self.row_iter().filter_map(|tile| match tile {
+            Tile::Definite(_) => None,
+            Tile::Indefinite(states) => Some(states),
+        })
+    }
+
+    /// Returns every state any tile in this span may be, counting a definite tile as its one state
+    pub fn union_states(&self) -> BTreeSet<T> {
+        let mut states = BTreeSet::new();
+        for tile in self.row_iter() {
+            match tile {
+                Tile::Definite(state) => {
+                    states.insert(state.clone());
+                }
+                Tile::Indefinite(tile_states) => states.extend(tile_states.iter().cloned()),
+            }
+        }
+        states
+    }
+
+    /// Returns the states every tile in this span may be, counting a definite tile as its one
+    /// state
+    ///
+    /// An empty span gives an empty set
+    pub fn intersect_states(&self) -> BTreeSet<T> {
+        let mut tiles = self.row_iter();
+        let mut states = match tiles.next() {
+            Some(Tile::Definite(state)) => BTreeSet::from([state.clone()]),
+            Some(Tile::Indefinite(states)) => states.clone(),
+            None => return BTreeSet::new(),
+        };
+        for tile in tiles {
+            match tile {
+                Tile::Definite(state) => states.retain(|s| s == state),
+                Tile::Indefinite(tile_states) => states.retain(|s| tile_states.contains(s)),
+            }
+        }
+        states
+    }
 }
 
 
 /// An iterator for the rows in a [Span]
-pub struct RowIter<'span, 'wfc, T: State> where 'span: 'wfc {
+pub struct RowIter<'span, 'wfc, T: State> {
     span: &'span Span<'wfc, T>,
     y: usize,
     x: usize,
 }
 
-impl<'span, 'wfc, T: State> Iterator for RowIter<'span, 'wfc, T> where 'span: 'wfc {
+impl<'span, 'wfc, T: State> Iterator for RowIter<'span, 'wfc, T> {
     type Item = &'wfc Tile<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.y < self.span.0.len() {
-            let out = self.span.0[self.y];
-            if self.x < out.len() {
-                let out = &out[self.x];
+        // Rows may differ in length once spans are concatenated, so step over each one's end
+        while let Some(row) = self.span.0.get(self.y) {
+            if let Some(tile) = row.get(self.x) {
                 self.x += 1;
-                Some(out)
-            } else {
-                self.y += 1;
-                self.x = 1;
-                if self.y < self.span.0.len() {
-                    Some(&self.span.0[self.y][0])
-                } else {
-                    None
-                }
+                return Some(*tile);
             }
-        } else {
-            None
+            self.y += 1;
+            self.x = 0;
         }
+        None
     }
 }
 
@@ -236,32 +654,32 @@ impl<'span, 'wfc, T: State> Iterator for ColIter<'span, 'wfc, T> {
     type Item = &'wfc Tile<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match (self.x_idx, self.y_idx) {
-            (x, y) if x == self.span.width() && y == self.span.height() => None,
-            (_, y) if y == self.span.height() => {
-                self.y_idx = 1;
-                self.x_idx += 1;
-                Some(self.x_idx)
-                    .filter(|x| *x != self.span.width())
-                    .map(|x| &self.span.0[0][x])
-            }
-            (_, y) => {
+        // Rows may differ in length once spans are concatenated, so skip those too short
+        while self.x_idx < self.span.width() {
+            while let Some(row) = self.span.0.get(self.y_idx) {
                 self.y_idx += 1;
-                Some(&self.span.0[y][self.x_idx])
+                if let Some(tile) = row.get(self.x_idx) {
+                    return Some(*tile);
+                }
             }
+            self.x_idx += 1;
+            self.y_idx = 0;
         }
+        None
     }
 }
 
 #[cfg(test)]
 mod test {
     use std::collections::BTreeSet;
-    use crate::wfc::{Tile, Wfc, WfcRules, WfcView};
+    use crate::wfc::{Direction, EdgeFlags, Tile, Topology, Wfc, WfcRules, WfcView};
 
     #[derive(Debug)]
     struct S;
 
     impl WfcRules<i32> for S {
+        type Cache = ();
+
         fn get_states(&self, _: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
             BTreeSet::from([0])
         }
@@ -271,6 +689,63 @@ mod test {
         Wfc::new(4, 4, (0..16).map(Tile::Definite).collect(), S)
     }
 
+    /// Allows every state below [max]
+    #[derive(Debug)]
+    struct Below {
+        max: i32,
+    }
+
+    impl WfcRules<i32> for Below {
+        type Cache = ();
+
+        fn get_states(&self, map: WfcView<'_, i32, Self>) -> BTreeSet<i32> {
+            states_below(&map)
+        }
+    }
+
+    fn states_below(view: &WfcView<'_, i32, Below>) -> BTreeSet<i32> {
+        (0..view.rules().max).collect()
+    }
+
+    #[test]
+    fn diamond() {
+        let wfc = Wfc::new(5, 5, (0..25).map(Tile::Definite).collect(), S);
+        let center = wfc.view(12).diamond(2);
+        assert_eq!(center.len(), 13);
+        assert!(center.iter().all(|(x, y, tile)| **tile == Tile::Definite((y * 5 + x) as i32)));
+        let xys = center.iter().map(|(x, y, _)| (*x, *y)).collect::<Vec<_>>();
+        assert_eq!(xys, vec![
+            (2, 0),
+            (1, 1), (2, 1), (3, 1),
+            (0, 2), (1, 2), (2, 2), (3, 2), (4, 2),
+            (1, 3), (2, 3), (3, 3),
+            (2, 4),
+        ]);
+
+        let corner = wfc.view(0).diamond(2).iter().map(|(x, y, _)| (*x, *y)).collect::<Vec<_>>();
+        assert_eq!(corner, vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)]);
+        assert_eq!(wfc.view(7).diamond(0).len(), 1);
+    }
+
+    #[test]
+    fn row_outward() {
+        let wfc = Wfc::new(5, 2, (0..10).map(Tile::Definite).collect(), S);
+        let cols = |idx| wfc.view(idx).row_outward().map(|(col, _)| col).collect::<Vec<_>>();
+        assert_eq!(cols(2), vec![2, 3, 1, 4, 0]);
+        assert_eq!(cols(5), vec![0, 1, 2, 3, 4]);
+        assert_eq!(cols(8), vec![3, 4, 2, 1, 0]);
+        let tiles = wfc.view(7).row_outward().map(|(_, tile)| tile.clone()).collect::<Vec<_>>();
+        assert_eq!(tiles, [7, 8, 6, 9, 5].map(Tile::Definite));
+    }
+
+    #[test]
+    fn rules() {
+        let tiles = vec![Tile::Indefinite(BTreeSet::from([0, 1, 2, 3])); 4];
+        let wfc = Wfc::new(2, 2, tiles, Below { max: 3 });
+        assert_eq!(wfc.view(3).rules().max, 3);
+        assert_eq!(states_below(&wfc.view(0)), BTreeSet::from([0, 1, 2]));
+    }
+
     #[test]
     fn row_iter() {
         let wfc = wfc();
@@ -356,6 +831,129 @@ mod test {
         assert_eq!(iter.next(), None);
     }
 
+    #[test]
+    #[allow(deprecated)]
+    fn collapsed_values() {
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[2] = Tile::Indefinite(BTreeSet::from([2, 3]));
+        tiles[5] = Tile::Indefinite(BTreeSet::from([5]));
+        let wfc = Wfc::new(4, 4, tiles, S);
+        let view = wfc.view(0);
+
+        let span = view.span(1..3, 0..3);
+        assert_eq!(span.count_definite(), 4);
+        assert_eq!(span.collapsed_values().collect::<Vec<_>>(), vec![&1, &6, &9, &10]);
+    }
+
+    #[test]
+    fn definite_iter() {
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[2] = Tile::Indefinite(BTreeSet::from([2, 3]));
+        tiles[5] = Tile::Indefinite(BTreeSet::from([5]));
+        let wfc = Wfc::new(4, 4, tiles, S);
+        let view = wfc.view(0);
+
+        let span = view.span(1..3, 0..3);
+        assert_eq!(span.definite_iter().collect::<Vec<_>>(), vec![&1, &6, &9, &10]);
+        assert_eq!(view.span(2..3, 0..1).definite_iter().next(), None);
+    }
+
+    #[test]
+    fn indefinite_iter() {
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[2] = Tile::Indefinite(BTreeSet::from([2, 3]));
+        tiles[5] = Tile::Indefinite(BTreeSet::from([5]));
+        let wfc = Wfc::new(4, 4, tiles, S);
+        let view = wfc.view(0);
+
+        let span = view.span(1..3, 0..3);
+        assert_eq!(span.indefinite_iter().collect::<Vec<_>>(), vec![&BTreeSet::from([2, 3]), &BTreeSet::from([5])]);
+        assert_eq!(view.span(0..1, 0..4).indefinite_iter().next(), None);
+    }
+
+    #[test]
+    fn edges() {
+        let wfc = Wfc::new(4, 3, (0..12).map(Tile::Definite).collect(), S);
+        for (x, y, edges) in [
+            (0, 0, Direction::North, Direction::West),
+            (3, 0, Direction::North, Direction::East),
+            (0, 2, Direction::South, Direction::West),
+            (3, 2, Direction::South, Direction::East),
+        ].map(|(x, y, a, b)| (x, y, EdgeFlags::from(a) | EdgeFlags::from(b))) {
+            let view = wfc.view_xy(x, y);
+            assert_eq!(view.on_edge(), edges);
+            assert!(view.is_corner() && view.is_border());
+        }
+
+        let edge = wfc.view_xy(2, 0);
+        assert_eq!(edge.on_edge(), EdgeFlags::from(Direction::North));
+        assert_eq!(edge.on_edge().len(), 1);
+        assert!(edge.is_border() && !edge.is_corner());
+
+        let interior = wfc.view_xy(1, 1);
+        assert_eq!(interior.on_edge(), EdgeFlags::NONE);
+        assert!(!interior.is_border() && !interior.is_corner());
+
+        let wrapping = Wfc::new(4, 3, (0..12).map(Tile::Definite).collect(), S).with_wrapping(true);
+        assert!(!wrapping.view(0).is_border());
+    }
+
+    #[test]
+    fn concat() {
+        let wfc = Wfc::new(4, 4, (0..16).map(Tile::Definite).collect(), S);
+        let view = wfc.view(0);
+        let span = view.row_span_at(0, 1..3).concat(view.row_span_at(3, 0..3));
+        assert_eq!(span.height(), 2);
+        assert_eq!(span.definite_iter().copied().collect::<Vec<_>>(), [1, 2, 12, 13, 14]);
+        assert_eq!(span.union_states(), BTreeSet::from([1, 2, 12, 13, 14]));
+
+        let empty = view.span_clamped(5..6, 0..1);
+        assert_eq!(empty.concat(view.row_span_at(1, 0..1)).row_iter().count(), 1);
+    }
+
+    #[test]
+    fn concat_ragged() {
+        let wfc = Wfc::new(4, 4, (0..16).map(Tile::Definite).collect(), S);
+        let view = wfc.view(0);
+        let values = |tiles: &mut dyn Iterator<Item=&Tile<i32>>| {
+            tiles.map(|tile| *tile.as_definite()).collect::<Vec<_>>()
+        };
+
+        // Longer first, then shorter first
+        for (span, cols) in [
+            (view.row_span_at(0, 0..3).concat(view.row_span_at(1, 0..1)), vec![vec![0, 4], vec![1], vec![2]]),
+            (view.row_span_at(1, 0..1).concat(view.row_span_at(0, 0..3)), vec![vec![4, 0], vec![1], vec![2]]),
+        ] {
+            assert_eq!(span.width(), 3);
+            assert_eq!(span.cols().map(|mut col| values(&mut col)).collect::<Vec<_>>(), cols);
+            assert_eq!(values(&mut span.col_iter()), cols.concat());
+
+            let transposed = span.transpose();
+            assert_eq!(transposed.height(), 3);
+            assert_eq!(transposed.rows().map(|mut row| values(&mut row)).collect::<Vec<_>>(), cols);
+        }
+    }
+
+    #[test]
+    fn fold_states() {
+        let mut tiles = (0..16).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[1] = Tile::Indefinite(BTreeSet::from([2, 3, 7]));
+        tiles[2] = Tile::Indefinite(BTreeSet::from([3, 5]));
+        tiles[3] = Tile::Indefinite(BTreeSet::from([3, 5, 9]));
+        let wfc = Wfc::new(4, 4, tiles, S);
+        let view = wfc.view(0);
+
+        let row = view.row_span_at(0, 1..4);
+        assert_eq!(row.union_states(), BTreeSet::from([2, 3, 5, 7, 9]));
+        assert_eq!(row.intersect_states(), BTreeSet::from([3]));
+
+        let mixed = view.row_span_at(0, 0..4);
+        assert_eq!(mixed.union_states(), BTreeSet::from([0, 2, 3, 5, 7, 9]));
+        assert_eq!(mixed.intersect_states(), BTreeSet::new());
+        assert_eq!(view.row_span_at(1, 2..3).intersect_states(), BTreeSet::from([6]));
+        assert_eq!(view.span_clamped(5..6, 0..1).intersect_states(), BTreeSet::new());
+    }
+
     #[test]
     fn col_span() {
         let wfc = wfc();
@@ -368,4 +966,219 @@ mod test {
         assert_eq!(iter.next(), Some(&Tile::Definite(8)));
         assert_eq!(iter.next(), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn diagonals() {
+        let wfc = wfc();
+
+        let view = wfc.view(5);
+        let down = view.diagonal_down();
+        assert_eq!(down.row_iter().collect::<Vec<_>>(), [0, 5, 10, 15].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+        let up = view.diagonal_up();
+        assert_eq!(up.row_iter().collect::<Vec<_>>(), [8, 5, 2].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+
+        let view = wfc.view(10);
+        let down = view.diagonal_down();
+        assert_eq!(down.row_iter().collect::<Vec<_>>(), [0, 5, 10, 15].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+        let up = view.diagonal_up();
+        assert_eq!(up.row_iter().collect::<Vec<_>>(), [13, 10, 7].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn diagonals_clamp() {
+        let wfc = wfc();
+
+        let view = wfc.view(3);
+        assert_eq!(view.diagonal_down().row_iter().collect::<Vec<_>>(), vec![&Tile::Definite(3)]);
+        assert_eq!(view.diagonal_up().row_iter().collect::<Vec<_>>(), [12, 9, 6, 3].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn section() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+
+        let section = view.section_at(2, 2, 3, 2);
+        assert_eq!(section.row_iter().collect::<Vec<_>>(), [10, 11, 14, 15].iter()
+            .map(|i| &wfc.map[*i])
+            .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wide_grid() {
+        let wfc = Wfc::new(6, 2, (0..12).map(Tile::Definite).collect(), S);
+        let view = wfc.view(0);
+
+        let col = view.col_at(5);
+        assert_eq!(col.row_iter().collect::<Vec<_>>(), vec![&Tile::Definite(5), &Tile::Definite(11)]);
+
+        let row = view.row_at(1);
+        assert_eq!(row.row_iter().collect::<Vec<_>>(), (6..12).map(|i| &wfc.map[i]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn transpose() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+
+        let span = view.span(1..3, 0..3);
+        let transposed = span.transpose();
+        assert_eq!(transposed.width(), 3);
+        assert_eq!(transposed.height(), 2);
+        assert_eq!(transposed.row_iter().collect::<Vec<_>>(), span.col_iter().collect::<Vec<_>>());
+        assert_eq!(transposed.col_iter().collect::<Vec<_>>(), span.row_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn index_of() {
+        let wfc = Wfc::new(6, 2, (0..12).map(Tile::Definite).collect(), S);
+        let view = wfc.view(0);
+        for y in 0..2 {
+            for x in 0..6 {
+                assert_eq!(view.index_of(x, y), wfc.xy_pair(x, y));
+            }
+        }
+        assert_eq!(wfc.view(9).self_index(), 9);
+    }
+
+    #[test]
+    fn get_non_square() {
+        let wfc = Wfc::new(6, 2, (0..12).map(Tile::Definite).collect(), S);
+        assert_eq!(wfc.view_xy(2, 1).get(), &wfc[6 + 2]);
+        assert_eq!(wfc.view(0).get_at(5, 1), &Tile::Definite(11));
+        for idx in 0..12 {
+            assert_eq!(wfc.view(idx).get(), &Tile::Definite(idx as i32));
+        }
+    }
+
+    #[test]
+    fn view_xy() {
+        let wfc = Wfc::new(6, 2, (0..12).map(Tile::Definite).collect(), S);
+        assert_eq!(wfc.view_xy(2, 1).self_index(), wfc.xy_pair(2, 1));
+        assert_eq!(wfc.view_xy(2, 1).pos(), wfc.view(8).pos());
+    }
+
+    #[test]
+    fn rows_cols() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+
+        let span = view.span(0..3, 1..3);
+        let rows = span.rows()
+            .map(|row| row.collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![
+            vec![&Tile::Definite(4), &Tile::Definite(5), &Tile::Definite(6)],
+            vec![&Tile::Definite(8), &Tile::Definite(9), &Tile::Definite(10)],
+        ]);
+
+        let cols = span.cols()
+            .map(|col| col.collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(cols, vec![
+            vec![&Tile::Definite(4), &Tile::Definite(8)],
+            vec![&Tile::Definite(5), &Tile::Definite(9)],
+            vec![&Tile::Definite(6), &Tile::Definite(10)],
+        ]);
+    }
+
+    #[test]
+    fn normalized_pos() {
+        let wfc = Wfc::new(5, 5, (0..25).map(Tile::Definite).collect(), S);
+        assert_eq!(wfc.view(0).normalized_pos(), (0.0, 0.0));
+        assert_eq!(wfc.view(24).normalized_pos(), (1.0, 1.0));
+        assert_eq!(wfc.view(4).normalized_pos(), (1.0, 0.0));
+
+        let (x, y) = wfc.view(12).normalized_pos();
+        assert!((x - 0.5).abs() < 1e-9 && (y - 0.5).abs() < 1e-9);
+
+        let wfc = Wfc::new(3, 1, (0..3).map(Tile::Definite).collect(), S);
+        assert_eq!(wfc.view(2).normalized_pos(), (1.0, 0.0));
+    }
+
+    #[test]
+    fn span_get() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+
+        let span = view.span(1..3, 0..3);
+        assert_eq!(span.get(0, 0), Some(&Tile::Definite(1)));
+        assert_eq!(span.get(1, 2), Some(&Tile::Definite(10)));
+        assert_eq!(span.at(1, 1), &Tile::Definite(6));
+        assert_eq!(span.get(2, 0), None);
+        assert_eq!(span.get(0, 3), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn span_at_out_of_range() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+        view.span(1..3, 0..3).at(2, 0);
+    }
+
+    #[test]
+    fn neighbors() {
+        let wfc = wfc();
+        assert_eq!(wfc.view(5).neighbors(), wfc.view(5).neighbors_4());
+
+        let wfc = wfc.with_topology(Topology::HexPointy);
+        assert_eq!(wfc.view(9).neighbors(), vec![5, 10, 13, 12, 8, 4]);
+        assert_eq!(wfc.view(9).neighbors_4(), vec![5, 10, 13, 8]);
+    }
+
+    #[test]
+    fn definite_in() {
+        let mut tiles = (0..4).map(Tile::Definite).collect::<Vec<_>>();
+        tiles[3] = Tile::Indefinite(BTreeSet::from([0, 1]));
+        let wfc = Wfc::new(2, 2, tiles, S);
+
+        let view = wfc.view(0);
+        assert_eq!(view.definite_in(Direction::North), None);
+        assert_eq!(view.definite_in(Direction::West), None);
+        assert_eq!(view.definite_in(Direction::East), Some(&1));
+        assert_eq!(view.definite_in(Direction::South), Some(&2));
+        assert_eq!(wfc.view(1).definite_in(Direction::South), None);
+    }
+
+    #[test]
+    fn span_clamped() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+
+        let span = view.span_clamped(2..10, 3..usize::MAX);
+        assert_eq!((span.width(), span.height()), (2, 1));
+        assert_eq!(span.row_iter().collect::<Vec<_>>(), vec![&Tile::Definite(14), &Tile::Definite(15)]);
+
+        let span = view.span_clamped(4..8, 0..2);
+        assert_eq!((span.width(), span.height()), (0, 0));
+        assert_eq!(span.row_iter().next(), None);
+    }
+
+    #[test]
+    fn full_spans() {
+        let wfc = wfc();
+        let view = wfc.view(0);
+
+        let col = view.col_span_at(3, 0..4);
+        assert_eq!(col.definite_iter().collect::<Vec<_>>(), vec![&3, &7, &11, &15]);
+        let row = view.row_span_at(3, 0..4);
+        assert_eq!(row.definite_iter().collect::<Vec<_>>(), vec![&12, &13, &14, &15]);
+    }
+
+    #[test]
+    #[should_panic(expected = "y-range must be inside of the map's height")]
+    fn col_span_past_height() {
+        wfc().view(0).col_span_at(0, 0..5);
+    }
+}